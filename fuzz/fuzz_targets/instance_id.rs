@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/id.rs"]
+mod id;
+
+use id::{InstanceId, RoomId};
+
+fuzz_target!(|s: &str| {
+    if let Ok(instance) = s.parse::<InstanceId>() {
+        assert_eq!(instance.to_string().parse::<InstanceId>().unwrap(), instance);
+    }
+    if let Ok(room) = s.parse::<RoomId>() {
+        assert_eq!(room.to_string().parse::<RoomId>().unwrap(), room);
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/id.rs"]
+mod id;
+#[allow(dead_code)]
+#[path = "../../src/log/parse.rs"]
+mod parse;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Some(event) = parse::parse_line(line, true) {
+        if let parse::LogEventKind::JoiningRoom(room) = event.kind {
+            assert_eq!(room.to_string().parse::<id::RoomId>().unwrap(), room);
+        }
+    }
+});
@@ -0,0 +1,99 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::bail;
+use chrono::{NaiveDateTime, TimeDelta};
+use encoding_rs::Encoding;
+
+use crate::{
+    api::VrcApiClient,
+    id::{RoomId, WorldId},
+    log::{self, LogEventKind},
+};
+
+/// Prints every world visit found in a directory of old log files.
+pub async fn run(
+    path: &Path,
+    encoding: &'static Encoding,
+    vrc_api: &VrcApiClient,
+) -> anyhow::Result<()> {
+    let files = log::archived_log_files(path).await?;
+    if files.is_empty() {
+        bail!("no log files found in {}", path.display());
+    }
+
+    let mut timeline = Timeline {
+        vrc_api,
+        world_names: HashMap::new(),
+    };
+    for file in files {
+        let events = log::read_log_file(&file, encoding).await?;
+        let mut current = None::<(NaiveDateTime, RoomId)>;
+        let mut last_timestamp = None;
+        for event in events {
+            last_timestamp = Some(event.timestamp);
+            match event.kind {
+                // Some builds log the same room twice while joining it.
+                LogEventKind::JoiningRoom(room_id)
+                    if current.as_ref().map(|(_, current)| current) != Some(&room_id) =>
+                {
+                    if let Some((joined_at, room_id)) = current.take() {
+                        timeline.print(joined_at, event.timestamp, &room_id).await;
+                    }
+                    current = Some((event.timestamp, room_id));
+                }
+                LogEventKind::LeftRoom | LogEventKind::Kicked { .. } => {
+                    if let Some((joined_at, room_id)) = current.take() {
+                        timeline.print(joined_at, event.timestamp, &room_id).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+        // The game was closed or crashed without leaving the room.
+        if let (Some((joined_at, room_id)), Some(last_timestamp)) = (current, last_timestamp) {
+            timeline.print(joined_at, last_timestamp, &room_id).await;
+        }
+    }
+
+    Ok(())
+}
+
+struct Timeline<'a> {
+    vrc_api: &'a VrcApiClient,
+    world_names: HashMap<WorldId, Option<String>>,
+}
+
+impl Timeline<'_> {
+    async fn print(&mut self, joined_at: NaiveDateTime, left_at: NaiveDateTime, room_id: &RoomId) {
+        let world_name = match self.world_names.get(&room_id.world) {
+            Some(name) => name.clone(),
+            None => {
+                let name = self
+                    .vrc_api
+                    .get_world(room_id.world)
+                    .await
+                    .ok()
+                    .and_then(|world| world.name);
+                self.world_names.insert(room_id.world, name.clone());
+                name
+            }
+        };
+        println!(
+            "{}  {:>9}  {} ({})",
+            joined_at.format("%Y-%m-%d %H:%M:%S"),
+            format_duration(left_at - joined_at),
+            world_name.as_deref().unwrap_or("N/A"),
+            room_id.world,
+        );
+    }
+}
+
+fn format_duration(duration: TimeDelta) -> String {
+    let seconds = duration.num_seconds().max(0);
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
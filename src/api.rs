@@ -1,7 +1,21 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{anyhow, Context};
 use async_trait::async_trait;
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
 use http::{header, Extensions, HeaderValue, StatusCode};
 use http_cache_reqwest::{
     CACacheManager, Cache, CacheMode, CacheOptions, HttpCache, HttpCacheOptions,
@@ -10,23 +24,67 @@ use reqwest::{Client, Request, Response, Url};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Middleware, Next, RequestBuilder};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
 use reqwest_tracing::{SpanBackendWithUrl, TracingMiddleware};
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Serialize,
+};
+use tokio::sync::Semaphore;
+use tracing::{debug, info, warn};
+
+use crate::{
+    id::{UserId, WorldId},
+    metrics, output,
+};
+
+/// The number of worlds kept in memory before expired ones are removed.
+const MAX_CACHED_WORLDS: usize = 256;
+
+/// The file in the cache directory that keeps the VRChat session cookies between runs.
+const SESSION_FILE: &str = "vrchat-session.txt";
+
+/// The directory in the cache that world images are kept in.
+const IMAGE_CACHE_DIR: &str = "images";
 
-use crate::{UserId, WorldId};
+/// The cookies VRChat uses for a logged in session.
+const SESSION_COOKIES: [&str; 2] = ["auth", "twoFactorAuth"];
 
 #[derive(Clone)]
 pub struct VrcApiClient {
     base: Arc<Url>,
+    image_hosts: Arc<[String]>,
+    image_downloads: Arc<Mutex<HashMap<(WorldId, ImageKind), ImageDownload>>>,
+    /// Limits concurrent image downloads so they don't slow down world information requests.
+    image_download_permits: Arc<Semaphore>,
+    /// The directory that downloaded world images are kept in.
+    image_cache: Arc<Path>,
+    /// Worlds fetched recently, with when they were fetched.
+    worlds: Arc<Mutex<HashMap<WorldId, (Instant, World)>>>,
+    /// How long worlds are kept in `worlds`. Zero turns off the in-memory cache.
+    world_max_age: Duration,
+    world_fetches: Arc<WorldFetchCounts>,
+    /// The cookies sent with API requests once logged in.
+    session: Arc<Mutex<Option<HeaderValue>>>,
+    /// What [`Self::log_in`] was called with, to log in again when the session expires. Also
+    /// keeps more than one request from doing that at a time.
+    credentials: Arc<tokio::sync::Mutex<Option<Credentials>>>,
     api_reqwest: ClientWithMiddleware,
     asset_reqwest: ClientWithMiddleware,
+    /// Sends login requests, which must not be cached.
+    auth_reqwest: ClientWithMiddleware,
 }
 
 impl VrcApiClient {
     const USER_AGENT: &'static str =
         concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
 
-    pub fn new(cache: impl AsRef<Path>) -> Self {
+    pub fn new(
+        cache: impl AsRef<Path>,
+        image_hosts: Vec<String>,
+        image_download_concurrency: usize,
+        world_max_age: Duration,
+    ) -> Self {
         let base = Arc::new(Url::parse("https://vrchat.com/api/").unwrap());
+        let image_cache = cache.as_ref().join(IMAGE_CACHE_DIR).into();
 
         let direct = Client::builder()
             .user_agent(Self::USER_AGENT)
@@ -46,36 +104,249 @@ impl VrcApiClient {
                 ..Default::default()
             },
         }));
+        let session = Arc::new(Mutex::new(None));
         let api_reqwest = ClientBuilder::new(direct.clone())
             .with(RetryTransientMiddleware::new_with_policy(retry_policy))
             .with(TracingMiddleware::<SpanBackendWithUrl>::new())
-            .with_arc(cache.clone())
-            .with(AuthenticationMiddleware)
+            .with(SessionCacheBypassMiddleware {
+                session: session.clone(),
+            })
+            .with_arc(cache)
+            .with(AuthenticationMiddleware {
+                session: session.clone(),
+            })
             .with(AlwaysCacheMiddleware)
             .build();
 
+        let auth_reqwest = ClientBuilder::new(direct.clone())
+            .with(RetryTransientMiddleware::new_with_policy(retry_policy))
+            .with(TracingMiddleware::<SpanBackendWithUrl>::new())
+            .build();
+
+        // Images are kept in `image_cache` instead of the HTTP cache.
         let asset_reqwest = ClientBuilder::new(direct)
             .with(RetryTransientMiddleware::new_with_policy(retry_policy))
             .with(TracingMiddleware::<SpanBackendWithUrl>::new())
-            .with_arc(cache)
             .build();
 
         VrcApiClient {
             base,
+            image_hosts: image_hosts.into(),
+            image_downloads: Default::default(),
+            image_download_permits: Arc::new(Semaphore::new(image_download_concurrency.max(1))),
+            image_cache,
+            worlds: Default::default(),
+            world_max_age,
+            world_fetches: Default::default(),
+            session,
+            credentials: Default::default(),
             api_reqwest,
             asset_reqwest,
+            auth_reqwest,
         }
     }
 
-    async fn send<T>(&self, request: RequestBuilder) -> anyhow::Result<T>
+    /// Logs in to VRChat, reusing the session saved in the cache directory while it is valid.
+    /// Asks for a code on the console when the account has two-factor authentication.
+    pub async fn log_in(&self, username: &str, password: &str, cache: &Path) -> anyhow::Result<()> {
+        let mut credentials = self.credentials.lock().await;
+        *credentials = Some(Credentials {
+            username: username.to_owned(),
+            password: password.to_owned(),
+            cache: cache.to_owned(),
+        });
+        self.start_session(username, password, cache).await
+    }
+
+    /// Logs in again after VRChat rejected the `expired` session, unless another request
+    /// already has.
+    async fn renew_session(&self, expired: &HeaderValue) -> anyhow::Result<()> {
+        let credentials = self.credentials.lock().await;
+        let Some(Credentials {
+            username,
+            password,
+            cache,
+        }) = &*credentials
+        else {
+            return Err(anyhow!("not logged in"));
+        };
+        if self.session.lock().unwrap().as_ref() != Some(expired) {
+            return Ok(());
+        }
+        info!("VRChat session expired, logging in again");
+        self.start_session(username, password, cache).await
+    }
+
+    async fn start_session(
+        &self,
+        username: &str,
+        password: &str,
+        cache: &Path,
+    ) -> anyhow::Result<()> {
+        let session_path = cache.join(SESSION_FILE);
+        if let Ok(saved) = tokio::fs::read_to_string(&session_path).await {
+            let cookies: Vec<String> = saved.lines().map(Into::into).collect();
+            match self.current_user(&cookies, None).await {
+                Ok((user, _)) if user.requires_two_factor_auth.is_empty() => {
+                    info!(name = ?user.display_name, "Logged in to VRChat with the saved session");
+                    return self.set_session(&cookies);
+                }
+                Ok(_) => debug!("saved VRChat session needs two-factor authentication"),
+                Err(error) => debug!(?error, "saved VRChat session is no longer valid"),
+            }
+        }
+
+        let (mut user, mut cookies) = self
+            .current_user(&[], Some((username, password)))
+            .await
+            .context("VRChat login failed")?;
+        if !user.requires_two_factor_auth.is_empty() {
+            let method = if user
+                .requires_two_factor_auth
+                .iter()
+                .any(|method| method == "emailOtp")
+            {
+                "emailotp"
+            } else {
+                "totp"
+            };
+            let code = prompt_two_factor_code(method).await?;
+            let mut url = self.base.as_ref().clone();
+            url.path_segments_mut().unwrap().pop().extend([
+                "1",
+                "auth",
+                "twofactorauth",
+                method,
+                "verify",
+            ]);
+            let (verify, verify_cookies): (TwoFactorVerify, _) = self
+                .auth_send(
+                    self.auth_reqwest
+                        .post(url)
+                        .json(&serde_json::json!({ "code": code })),
+                    &cookies,
+                )
+                .await
+                .context("two-factor authentication failed")?;
+            anyhow::ensure!(
+                verify.verified,
+                "the two-factor authentication code was rejected"
+            );
+            merge_cookies(&mut cookies, verify_cookies);
+            (user, _) = self.current_user(&cookies, None).await?;
+        }
+        info!(name = ?user.display_name, "Logged in to VRChat");
+
+        if let Err(error) = tokio::fs::create_dir_all(cache).await {
+            warn!(?error, "cache directory error");
+        }
+        if let Err(error) = output::write_atomic(&session_path, cookies.join("\n").as_bytes()).await
+        {
+            warn!(?error, path = ?session_path, "VRChat session write error");
+        }
+        self.set_session(&cookies)
+    }
+
+    /// Fetches the logged in user, with basic authentication when starting a new session.
+    async fn current_user(
+        &self,
+        cookies: &[String],
+        credentials: Option<(&str, &str)>,
+    ) -> anyhow::Result<(CurrentUser, Vec<String>)> {
+        let mut url = self.base.as_ref().clone();
+        url.path_segments_mut()
+            .unwrap()
+            .pop()
+            .extend(["1", "auth", "user"]);
+        let mut request = self.auth_reqwest.get(url);
+        if let Some((username, password)) = credentials {
+            // VRChat expects the credentials to be URL encoded before they are base64 encoded.
+            request = request.basic_auth(
+                encode_credential(username),
+                Some(encode_credential(password)),
+            );
+        }
+        let (user, new_cookies) = self.auth_send(request, cookies).await?;
+        let mut cookies = cookies.to_vec();
+        merge_cookies(&mut cookies, new_cookies);
+        Ok((user, cookies))
+    }
+
+    /// Sends a login request with the session cookies so far, returning the cookies it sets.
+    async fn auth_send<T>(
+        &self,
+        mut request: RequestBuilder,
+        cookies: &[String],
+    ) -> anyhow::Result<(T, Vec<String>)>
     where
         T: DeserializeOwned,
     {
+        if !cookies.is_empty() {
+            request = request.header(header::COOKIE, cookies.join("; "));
+        }
         let response = request.send().await.context("request error")?;
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED {
+            return Err(anyhow!("invalid username, password or session"));
+        } else if !status.is_success() {
+            return Err(anyhow!("unexpected status code {status}"));
+        }
+        let cookies = response
+            .headers()
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.split(';').next())
+            .filter(|cookie| {
+                cookie
+                    .split_once('=')
+                    .is_some_and(|(name, _)| SESSION_COOKIES.contains(&name.trim()))
+            })
+            .map(|cookie| cookie.trim().to_owned())
+            .collect();
+        Ok((response.json().await.context("invalid response")?, cookies))
+    }
+
+    /// Whether where-am-i is logged in to VRChat.
+    pub fn has_session(&self) -> bool {
+        self.session.lock().unwrap().is_some()
+    }
+
+    fn set_session(&self, cookies: &[String]) -> anyhow::Result<()> {
+        let value = HeaderValue::try_from(cookies.join("; ")).context("invalid session cookie")?;
+        *self.session.lock().unwrap() = Some(value);
+        Ok(())
+    }
+
+    async fn send<T>(&self, request: RequestBuilder) -> anyhow::Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let retry = request.try_clone();
+        let session = self.session.lock().unwrap().clone();
+        let mut response = request.send().await.context("request error")?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            if let (Some(session), Some(retry)) = (session, retry) {
+                if let Err(error) = self.renew_session(&session).await {
+                    warn!(?error, "Could not log in to VRChat again");
+                } else {
+                    response = retry.send().await.context("request error")?;
+                }
+            }
+        }
 
         let status = response.status();
         if status.is_success() {
             response.json().await.context("invalid response")
+        } else if status == StatusCode::UNAUTHORIZED {
+            let url = response.url().clone();
+            warn!(
+                %url,
+                "VRChat requires authentication for this request, but where-am-i is not logged in"
+            );
+            Err(anyhow!(
+                "authentication required for {url}; set username and password to log in"
+            ))
         } else if status.is_client_error() {
             let error: ClientError = response
                 .json()
@@ -91,53 +362,483 @@ impl VrcApiClient {
         }
     }
 
-    pub async fn get_world(&self, world: WorldId) -> anyhow::Result<World> {
+    fn world_request(&self, world: WorldId) -> RequestBuilder {
         let mut url = self.base.as_ref().clone();
         url.path_segments_mut()
             .unwrap()
             .pop()
             .extend(["1", "worlds", &world.to_string()]);
+        self.api_reqwest.get(url)
+    }
+
+    pub async fn get_world(&self, world: WorldId) -> anyhow::Result<World> {
+        if let Some(cached) = self.get_memory_cached_world(world) {
+            return Ok(cached);
+        }
+        let info: World = match self.send(self.world_request(world)).await {
+            Ok(info) => {
+                self.world_fetches.successes.fetch_add(1, Ordering::Relaxed);
+                info
+            }
+            Err(error) => {
+                self.world_fetches.failures.fetch_add(1, Ordering::Relaxed);
+                return Err(error);
+            }
+        };
+        if !self.world_max_age.is_zero() {
+            let mut worlds = self.worlds.lock().unwrap();
+            if worlds.len() >= MAX_CACHED_WORLDS {
+                worlds.retain(|_, (fetched_at, _)| fetched_at.elapsed() < self.world_max_age);
+                if worlds.len() >= MAX_CACHED_WORLDS {
+                    worlds.clear();
+                }
+            }
+            worlds.insert(world, (Instant::now(), info.clone()));
+        }
+        Ok(info)
+    }
+
+    fn get_memory_cached_world(&self, world: WorldId) -> Option<World> {
+        let worlds = self.worlds.lock().unwrap();
+        let (fetched_at, info) = worlds.get(&world)?;
+        (fetched_at.elapsed() < self.world_max_age).then(|| info.clone())
+    }
+
+    /// Looks up a user's public profile. VRChat only returns users to logged in clients, so this
+    /// fails when where-am-i is not logged in.
+    pub async fn get_user(&self, user: UserId) -> anyhow::Result<User> {
+        let mut url = self.base.as_ref().clone();
+        url.path_segments_mut()
+            .unwrap()
+            .pop()
+            .extend(["1", "users", &user.to_string()]);
         self.send(self.api_reqwest.get(url)).await
     }
 
+    /// Counts the public worlds published by a user. VRChat only lists them for logged in
+    /// clients, so this fails when where-am-i is not logged in.
+    pub async fn get_author_world_count(&self, author: UserId) -> anyhow::Result<usize> {
+        const PAGE_SIZE: usize = 100;
+        const MAX_PAGES: usize = 10;
+
+        let mut url = self.base.as_ref().clone();
+        url.path_segments_mut()
+            .unwrap()
+            .pop()
+            .extend(["1", "worlds"]);
+        let author = author.to_string();
+        let mut count = 0;
+        for page in 0..MAX_PAGES {
+            let worlds: Vec<IgnoredAny> = self
+                .send(self.api_reqwest.get(url.clone()).query(&[
+                    ("userId", author.as_str()),
+                    ("releaseStatus", "public"),
+                    ("n", &PAGE_SIZE.to_string()),
+                    ("offset", &(page * PAGE_SIZE).to_string()),
+                ]))
+                .await?;
+            count += worlds.len();
+            if worlds.len() < PAGE_SIZE {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn write_metrics(&self, out: &mut String) {
+        metrics::write_counter(
+            out,
+            "where_am_i_world_fetch_successes_total",
+            "World information requests that succeeded, including HTTP cache hits.",
+            self.world_fetches.successes.load(Ordering::Relaxed),
+        );
+        metrics::write_counter(
+            out,
+            "where_am_i_world_fetch_failures_total",
+            "World information requests that failed.",
+            self.world_fetches.failures.load(Ordering::Relaxed),
+        );
+    }
+
+    /// Looks up a world in the HTTP cache without contacting VRChat.
+    pub async fn get_cached_world(&self, world: WorldId) -> Option<World> {
+        if let Some(cached) = self.get_memory_cached_world(world) {
+            return Some(cached);
+        }
+        self.send(
+            self.world_request(world)
+                .with_extension(CacheMode::OnlyIfCached),
+        )
+        .await
+        .ok()
+    }
+
+    /// Fetches a world the same way as [`Self::get_world`] and reports how the request went.
+    pub async fn self_test(&self, world: WorldId) -> SelfTest {
+        let start = Instant::now();
+        let response = self.world_request(world).send().await;
+        let mut result = SelfTest {
+            ok: false,
+            world_id: world,
+            latency_ms: 0,
+            status: None,
+            cache: None,
+            auth_accepted: None,
+            world_name: None,
+            error: None,
+        };
+        match response {
+            Ok(response) => {
+                let status = response.status();
+                result.status = Some(status.as_u16());
+                result.cache = response
+                    .headers()
+                    .get("x-cache")
+                    .and_then(|value| value.to_str().ok())
+                    .map(Into::into);
+                result.auth_accepted =
+                    Some(status != StatusCode::UNAUTHORIZED && status != StatusCode::FORBIDDEN);
+                match response.error_for_status() {
+                    Ok(response) => match response.json::<World>().await {
+                        Ok(world) => {
+                            result.ok = true;
+                            result.world_name = world.name;
+                        }
+                        Err(error) => result.error = Some(format!("invalid response: {error}")),
+                    },
+                    Err(error) => result.error = Some(error.to_string()),
+                }
+            }
+            Err(error) => result.error = Some(format!("request error: {error:#}")),
+        }
+        result.latency_ms = start.elapsed().as_millis() as u64;
+        result
+    }
+
+    /// Downloads a world's image, sharing the download with concurrent requests for the same world.
+    async fn shared_world_image(
+        &self,
+        world: WorldId,
+        kind: ImageKind,
+    ) -> anyhow::Result<WorldImage> {
+        // Overlays tend to request the image all at once after a world change, so concurrent
+        // requests for the same world share a single download.
+        let download = self
+            .image_downloads
+            .lock()
+            .unwrap()
+            .entry((world, kind))
+            .or_insert_with(|| {
+                let this = self.clone();
+                async move {
+                    this.download_world_image(world, kind)
+                        .await
+                        .map_err(Arc::new)
+                }
+                .boxed()
+                .shared()
+            })
+            .clone();
+        let image = download.clone().await;
+        // A newer download may have replaced this one if it finished while this request was
+        // waiting to run again, and that download must not be forgotten.
+        let mut downloads = self.image_downloads.lock().unwrap();
+        if downloads
+            .get(&(world, kind))
+            .is_some_and(|current| current.ptr_eq(&download))
+        {
+            downloads.remove(&(world, kind));
+        }
+        image.map_err(|error| anyhow!("{error:#}"))
+    }
+
     pub async fn get_world_image(
         &self,
         world: WorldId,
+        kind: ImageKind,
     ) -> anyhow::Result<axum::response::Response> {
-        let info = self.get_world(world).await?;
-        let Some(image_url) = info.image_url else {
-            return Ok(axum::response::Response::builder()
+        let response = axum::response::Response::builder();
+        Ok(match self.shared_world_image(world, kind).await? {
+            WorldImage::Missing => response
                 .status(StatusCode::NOT_FOUND)
-                .body(Default::default())?);
+                .body(Default::default())?,
+            WorldImage::Disallowed => response
+                .status(StatusCode::BAD_GATEWAY)
+                .body(Default::default())?,
+            WorldImage::Image {
+                content_type,
+                bytes,
+            } => {
+                let mut response = response.status(StatusCode::OK);
+                if let Some(content_type) = content_type {
+                    response = response.header(header::CONTENT_TYPE, content_type);
+                }
+                response.body(bytes.into())?
+            }
+        })
+    }
+
+    /// The world's image file, or `None` if it has no image that may be downloaded.
+    pub async fn get_world_image_bytes(&self, world: WorldId) -> anyhow::Result<Option<Bytes>> {
+        Ok(
+            match self.shared_world_image(world, ImageKind::Image).await? {
+                WorldImage::Image { bytes, .. } => Some(bytes),
+                WorldImage::Missing | WorldImage::Disallowed => None,
+            },
+        )
+    }
+
+    async fn download_world_image(
+        &self,
+        world: WorldId,
+        kind: ImageKind,
+    ) -> anyhow::Result<WorldImage> {
+        let info = self.get_world(world).await?;
+        let image_url = match kind {
+            ImageKind::Image => info.image_url,
+            ImageKind::Thumbnail => info.thumbnail_image_url,
         };
-        let mut upstream = self
-            .asset_reqwest
-            .get(image_url)
-            .send()
-            .await
-            .context("request error")?
-            .error_for_status()?;
-        let mut response = axum::response::Response::builder().status(StatusCode::OK);
+        let Some(image_url) = image_url else {
+            return Ok(WorldImage::Missing);
+        };
+        if !image_url
+            .host_str()
+            .is_some_and(|host| self.image_hosts.iter().any(|allowed| allowed == host))
         {
-            let upstream_headers = upstream.headers_mut();
-            let headers = response.headers_mut().unwrap();
-            if let Some(content_type) = upstream_headers.remove(header::CONTENT_TYPE) {
-                headers.insert(header::CONTENT_TYPE, content_type);
+            warn!(%image_url, "world image is not on an allowed host");
+            return Ok(WorldImage::Disallowed);
+        }
+        // The semaphore is never closed.
+        let _permit = self.image_download_permits.acquire().await.unwrap();
+
+        let cache_name = format!(
+            "{world}-{}",
+            image_url
+                .path_segments()
+                .and_then(|mut segments| segments.next_back())
+                .unwrap_or_default()
+                .replace(|c: char| !c.is_ascii_alphanumeric() && c != '.', "_"),
+        );
+        let bytes_path = self.image_cache.join(&cache_name);
+        let meta_path = self.image_cache.join(cache_name + ".json");
+        let cached = match tokio::fs::read(&meta_path).await {
+            Ok(meta) => serde_json::from_slice::<CachedImage>(&meta)
+                .ok()
+                .filter(|meta| meta.url == image_url),
+            Err(_) => None,
+        };
+        let cached = match cached {
+            Some(meta) => match tokio::fs::read(&bytes_path).await {
+                Ok(bytes) => Some((meta, Bytes::from(bytes))),
+                Err(error) => {
+                    debug!(?error, path = ?bytes_path, "cached world image read error");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        let mut request = self.asset_reqwest.get(image_url.clone());
+        if let Some((meta, bytes)) = &cached {
+            if meta.etag.is_none() && meta.last_modified.is_none() {
+                // Without validators, the image can't change without its URL changing.
+                return Ok(meta.to_image(bytes.clone()));
+            }
+            if let Some(etag) = &meta.etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &meta.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let upstream = match request.send().await.context("request error") {
+            Ok(upstream) => upstream,
+            Err(error) => match cached {
+                Some((meta, bytes)) => {
+                    warn!(?error, %image_url, "world image revalidation error, using cached image");
+                    return Ok(meta.to_image(bytes));
+                }
+                None => return Err(error),
+            },
+        };
+        if upstream.status() == StatusCode::NOT_MODIFIED {
+            if let Some((meta, bytes)) = cached {
+                return Ok(meta.to_image(bytes));
             }
         }
-        Ok(response.body(upstream.bytes().await?.into())?)
+        let upstream = upstream.error_for_status()?;
+        let header_value = |name| {
+            upstream
+                .headers()
+                .get(name)
+                .and_then(|value: &HeaderValue| value.to_str().ok())
+                .map(String::from)
+        };
+        let meta = CachedImage {
+            url: image_url,
+            content_type: header_value(header::CONTENT_TYPE),
+            etag: header_value(header::ETAG),
+            last_modified: header_value(header::LAST_MODIFIED),
+        };
+        let bytes = upstream.bytes().await?;
+
+        if let Err(error) = tokio::fs::create_dir_all(&self.image_cache).await {
+            warn!(?error, "image cache directory error");
+        } else if let Err(error) = output::write_atomic(&bytes_path, &bytes).await {
+            warn!(?error, path = ?bytes_path, "world image cache write error");
+        } else if let Err(error) =
+            output::write_atomic(&meta_path, &serde_json::to_vec(&meta).unwrap()).await
+        {
+            warn!(?error, path = ?meta_path, "world image cache write error");
+        }
+        Ok(meta.to_image(bytes))
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTest {
+    ok: bool,
+    world_id: WorldId,
+    latency_ms: u64,
+    status: Option<u16>,
+    /// The `x-cache` header from the HTTP cache, such as "HIT" or "MISS".
+    cache: Option<String>,
+    auth_accepted: Option<bool>,
+    world_name: Option<String>,
+    error: Option<String>,
+}
+
+/// Which of a world's images to download.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ImageKind {
+    Image,
+    /// A smaller version of the image.
+    Thumbnail,
+}
+
+/// What is known about a world image kept in the image cache, stored next to it.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedImage {
+    url: Url,
+    content_type: Option<String>,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CachedImage {
+    fn to_image(&self, bytes: Bytes) -> WorldImage {
+        WorldImage::Image {
+            content_type: self
+                .content_type
+                .as_deref()
+                .and_then(|content_type| HeaderValue::from_str(content_type).ok()),
+            bytes,
+        }
+    }
+}
+
+type ImageDownload = Shared<BoxFuture<'static, Result<WorldImage, Arc<anyhow::Error>>>>;
+
+#[derive(Clone)]
+enum WorldImage {
+    Missing,
+    Disallowed,
+    Image {
+        content_type: Option<HeaderValue>,
+        bytes: Bytes,
+    },
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct World {
     pub author_id: Option<UserId>,
     pub author_name: Option<String>,
+    /// The hard limit on players in an instance.
+    pub capacity: Option<u32>,
+    // VRChat sends these two in snake case, but they are sent on in camel case like the rest.
+    #[serde(alias = "created_at")]
+    pub created_at: Option<DateTime<Utc>>,
     pub description: Option<String>,
+    /// How many users have favorited the world.
+    pub favorites: Option<u64>,
+    pub heat: Option<u32>,
     pub image_url: Option<Url>,
     pub name: Option<String>,
+    /// The number of players the author suggests, which public instances are limited to.
+    pub recommended_capacity: Option<u32>,
+    /// "public" or "private".
+    pub release_status: Option<String>,
     pub thumbnail_image_url: Option<Url>,
+    #[serde(alias = "updated_at")]
+    pub updated_at: Option<DateTime<Utc>>,
+    pub visits: Option<u64>,
+    #[serde(default, skip_serializing)]
+    pub unity_packages: Vec<UnityPackage>,
+}
+
+impl World {
+    pub fn platform(&self) -> Platform {
+        let mut pc = false;
+        let mut quest = false;
+        for package in &self.unity_packages {
+            match package.platform.as_deref() {
+                Some("standalonewindows") => pc = true,
+                Some("android") => quest = true,
+                _ => {}
+            }
+        }
+        match (pc, quest) {
+            (true, true) => Platform::Both,
+            (true, false) => Platform::Pc,
+            (false, true) => Platform::Quest,
+            (false, false) => Platform::Unknown,
+        }
+    }
+
+    /// The size of the largest download for the world, when VRChat includes package sizes.
+    pub fn size_bytes(&self) -> Option<u64> {
+        self.unity_packages
+            .iter()
+            .filter_map(|package| package.size_in_bytes)
+            .max()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub display_name: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnityPackage {
+    pub platform: Option<String>,
+    pub size_in_bytes: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Platform {
+    Unknown,
+    Pc,
+    Quest,
+    #[serde(rename = "crossPlatform")]
+    Both,
+}
+
+impl Platform {
+    pub fn description(self) -> Option<&'static str> {
+        match self {
+            Platform::Unknown => None,
+            Platform::Pc => Some("PC only"),
+            Platform::Quest => Some("Quest only"),
+            Platform::Both => Some("PC and Quest"),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -150,7 +851,99 @@ struct ClientErrorInner {
     message: String,
 }
 
-struct AuthenticationMiddleware;
+/// How many world information requests succeeded and failed, for `/metrics`.
+#[derive(Default)]
+struct WorldFetchCounts {
+    successes: AtomicU64,
+    failures: AtomicU64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurrentUser {
+    display_name: Option<String>,
+    /// The two-factor authentication methods available, when a code is needed to finish logging in.
+    #[serde(default)]
+    requires_two_factor_auth: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct TwoFactorVerify {
+    verified: bool,
+}
+
+/// Replaces cookies with the same name, keeping the rest.
+fn merge_cookies(cookies: &mut Vec<String>, new: Vec<String>) {
+    for cookie in new {
+        let name = cookie.split('=').next().unwrap_or_default();
+        cookies.retain(|old| old.split('=').next() != Some(name));
+        cookies.push(cookie);
+    }
+}
+
+/// Percent-encodes everything except unreserved characters, like JavaScript's
+/// `encodeURIComponent`.
+fn encode_credential(credential: &str) -> String {
+    credential
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-_.!~*'()".contains(&byte) {
+                char::from(byte).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect()
+}
+
+/// Asks for a two-factor authentication code on the console.
+async fn prompt_two_factor_code(method: &'static str) -> anyhow::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        if method == "emailotp" {
+            eprint!("Enter the VRChat login code sent to your email: ");
+        } else {
+            eprint!("Enter the VRChat code from your authenticator app: ");
+        }
+        let mut code = String::new();
+        std::io::stdin()
+            .read_line(&mut code)
+            .context("could not read the two-factor authentication code")?;
+        Ok(code.trim().to_owned())
+    })
+    .await?
+}
+
+/// How to log in to VRChat again.
+struct Credentials {
+    username: String,
+    password: String,
+    cache: PathBuf,
+}
+
+/// Keeps responses to requests that carry the session out of the HTTP cache, which is stored
+/// on disk. Requests that set their own cache mode are left alone.
+struct SessionCacheBypassMiddleware {
+    session: Arc<Mutex<Option<HeaderValue>>>,
+}
+
+#[async_trait]
+impl Middleware for SessionCacheBypassMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        if self.session.lock().unwrap().is_some() && extensions.get::<CacheMode>().is_none() {
+            extensions.insert(CacheMode::NoStore);
+        }
+        next.run(req, extensions).await
+    }
+}
+
+struct AuthenticationMiddleware {
+    session: Arc<Mutex<Option<HeaderValue>>>,
+}
 
 #[async_trait]
 impl Middleware for AuthenticationMiddleware {
@@ -160,9 +953,12 @@ impl Middleware for AuthenticationMiddleware {
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> reqwest_middleware::Result<Response> {
+        // VRChat rejects requests without an auth cookie, even for public information.
         static DUMMY_AUTH: HeaderValue =
             HeaderValue::from_static("auth=JlE5Jldo5Jibnk5O5hTx6XVqsJu4WJ26");
-        req.headers_mut().append("Cookie", DUMMY_AUTH.clone());
+        let session = self.session.lock().unwrap().clone();
+        req.headers_mut()
+            .append("Cookie", session.unwrap_or_else(|| DUMMY_AUTH.clone()));
         next.run(req, extensions).await
     }
 }
@@ -188,3 +984,110 @@ impl Middleware for AlwaysCacheMiddleware {
         Ok(response)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the cache mode a request reaches the HTTP cache with, without sending it.
+    struct RecordCacheMode(Arc<Mutex<Option<CacheMode>>>);
+
+    #[async_trait]
+    impl Middleware for RecordCacheMode {
+        async fn handle(
+            &self,
+            _req: Request,
+            extensions: &mut Extensions,
+            _next: Next<'_>,
+        ) -> reqwest_middleware::Result<Response> {
+            *self.0.lock().unwrap() = extensions.get::<CacheMode>().copied();
+            Ok(Response::from(http::Response::new("")))
+        }
+    }
+
+    async fn cache_mode(
+        session: Option<HeaderValue>,
+        requested: Option<CacheMode>,
+    ) -> Option<CacheMode> {
+        let mode = Arc::new(Mutex::new(None));
+        let client = ClientBuilder::new(Client::new())
+            .with(SessionCacheBypassMiddleware {
+                session: Arc::new(Mutex::new(session)),
+            })
+            .with(RecordCacheMode(mode.clone()))
+            .build();
+        let mut request = client.get("http://localhost/api/1/users");
+        if let Some(requested) = requested {
+            request = request.with_extension(requested);
+        }
+        request.send().await.unwrap();
+        let mode = *mode.lock().unwrap();
+        mode
+    }
+
+    #[tokio::test]
+    async fn session_requests_bypass_cache() {
+        let session = HeaderValue::from_static("auth=authcookie_1234");
+        assert_eq!(cache_mode(None, None).await, None);
+        assert_eq!(
+            cache_mode(Some(session.clone()), None).await,
+            Some(CacheMode::NoStore)
+        );
+        assert_eq!(
+            cache_mode(Some(session), Some(CacheMode::OnlyIfCached)).await,
+            Some(CacheMode::OnlyIfCached)
+        );
+    }
+
+    #[test]
+    fn pc_only_world() {
+        let world: World = serde_json::from_str(
+            r#"{
+                "name": "The Black Cat",
+                "unityPackages": [
+                    {"platform": "standalonewindows", "unityVersion": "2022.3.6f1"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(world.platform(), Platform::Pc);
+    }
+
+    #[test]
+    fn cross_platform_world() {
+        let world: World = serde_json::from_str(
+            r#"{
+                "name": "The Black Cat",
+                "unityPackages": [
+                    {"platform": "standalonewindows", "unityVersion": "2022.3.6f1"},
+                    {"platform": "android", "unityVersion": "2022.3.6f1"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(world.platform(), Platform::Both);
+    }
+
+    #[test]
+    fn world_without_packages() {
+        let world: World = serde_json::from_str(r#"{"name": "The Black Cat"}"#).unwrap();
+        assert_eq!(world.platform(), Platform::Unknown);
+        assert_eq!(world.size_bytes(), None);
+    }
+
+    #[test]
+    fn world_size_is_largest_package() {
+        let world: World = serde_json::from_str(
+            r#"{
+                "name": "The Black Cat",
+                "unityPackages": [
+                    {"platform": "standalonewindows", "sizeInBytes": 47400000},
+                    {"platform": "android", "sizeInBytes": 9800000},
+                    {"platform": "android"}
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(world.size_bytes(), Some(47400000));
+    }
+}
@@ -0,0 +1,59 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::Context;
+use tokio::time::{interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+/// Stop counting after this many files so a huge cache doesn't keep the disk busy.
+const MAX_ENTRIES: usize = 100_000;
+
+pub async fn monitor_size(path: impl AsRef<Path>, period: Duration) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let mut interval = interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        match directory_size(path).await {
+            Ok(Some(bytes)) => info!(?path, bytes, "Cache size"),
+            Ok(None) => warn!(
+                ?path,
+                "Cache has more than {MAX_ENTRIES} entries, not measuring"
+            ),
+            Err(error) => warn!(?error, ?path, "Cache size error"),
+        }
+    }
+}
+
+async fn directory_size(path: &Path) -> anyhow::Result<Option<u64>> {
+    let mut pending: Vec<PathBuf> = vec![path.to_owned()];
+    let mut bytes = 0;
+    let mut entries = 0;
+    while let Some(directory) = pending.pop() {
+        let mut reader = match tokio::fs::read_dir(&directory).await {
+            Ok(reader) => reader,
+            // The cache is created on first use.
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => return Err(error).context("cache directory open error"),
+        };
+        while let Some(entry) = reader
+            .next_entry()
+            .await
+            .context("cache directory read error")?
+        {
+            entries += 1;
+            if entries > MAX_ENTRIES {
+                return Ok(None);
+            }
+            let metadata = entry.metadata().await.context("cache metadata error")?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                bytes += metadata.len();
+            }
+        }
+    }
+    Ok(Some(bytes))
+}
@@ -0,0 +1,224 @@
+use std::io::Cursor;
+
+use ab_glyph::{FontVec, PxScale};
+use fast_qr::{QRBuilder, ECL};
+use image::{imageops::FilterType, ImageFormat, Rgba, RgbaImage};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_text_mut, text_size},
+    rect::Rect,
+};
+use serde::Deserialize;
+
+/// Query parameters for `/api/world/current/card.png`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(default)]
+pub struct CardOptions {
+    pub width: u32,
+    pub height: u32,
+    pub qr: bool,
+}
+
+impl Default for CardOptions {
+    fn default() -> Self {
+        Self {
+            width: 1200,
+            height: 630,
+            qr: false,
+        }
+    }
+}
+
+impl CardOptions {
+    /// Keeps the card to a size that is quick to render.
+    pub fn clamped(self) -> Self {
+        Self {
+            width: self.width.clamp(200, 3840),
+            height: self.height.clamp(200, 2160),
+            ..self
+        }
+    }
+}
+
+pub struct CardContent {
+    pub image: Option<Vec<u8>>,
+    pub name: String,
+    pub author: Option<String>,
+    pub qr_url: Option<String>,
+}
+
+const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Draws the world image with its name, author and optionally a QR code, and encodes it as PNG.
+pub fn render(
+    content: CardContent,
+    font: &FontVec,
+    options: CardOptions,
+) -> anyhow::Result<Vec<u8>> {
+    let CardOptions { width, height, qr } = options;
+    let mut card = match &content.image {
+        Some(image) => image::load_from_memory(image)?
+            .resize_to_fill(width, height, FilterType::Triangle)
+            .to_rgba8(),
+        None => RgbaImage::from_pixel(width, height, Rgba([32, 32, 32, 255])),
+    };
+
+    let Layout {
+        band_height,
+        margin,
+        text_width,
+        qr_size,
+    } = layout(width, height, qr && content.qr_url.is_some());
+
+    // Darken the bottom of the image so the text is readable on any background.
+    for y in height - band_height..height {
+        for x in 0..width {
+            let pixel = card.get_pixel_mut(x, y);
+            for channel in &mut pixel.0[..3] {
+                *channel /= 3;
+            }
+        }
+    }
+
+    if let (Some(url), Some(qr_size)) = (&content.qr_url, qr_size) {
+        draw_qr(
+            &mut card,
+            url,
+            width - margin - qr_size,
+            height - margin - qr_size,
+            qr_size,
+        )?;
+    }
+
+    let name_scale = fit_scale(font, &content.name, band_height as f32 / 3.0, text_width);
+    let name_y = (height - band_height + margin) as i32;
+    draw_text_mut(
+        &mut card,
+        WHITE,
+        margin as i32,
+        name_y,
+        name_scale,
+        font,
+        &content.name,
+    );
+
+    if let Some(author) = &content.author {
+        let author = format!("by {author}");
+        let author_scale = fit_scale(font, &author, band_height as f32 / 5.0, text_width);
+        let author_y = name_y + name_scale.y as i32 + margin as i32 / 2;
+        draw_text_mut(
+            &mut card,
+            WHITE,
+            margin as i32,
+            author_y,
+            author_scale,
+            font,
+            &author,
+        );
+    }
+
+    let mut png = Vec::new();
+    card.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Where the parts of a card go.
+#[derive(Debug, PartialEq, Eq)]
+struct Layout {
+    /// The height of the darkened band at the bottom, which holds the text and QR code.
+    band_height: u32,
+    margin: u32,
+    text_width: u32,
+    /// The size of the QR code, or `None` when there is no room for it.
+    qr_size: Option<u32>,
+}
+
+fn layout(width: u32, height: u32, qr: bool) -> Layout {
+    let band_height = height / 3;
+    let margin = height / 20;
+    let text_width = width.saturating_sub(margin * 2);
+    let qr_size = band_height - margin;
+    // Narrow cards leave out the QR code rather than the text.
+    match text_width.checked_sub(qr_size + margin) {
+        Some(remaining) if qr && remaining >= qr_size => Layout {
+            band_height,
+            margin,
+            text_width: remaining,
+            qr_size: Some(qr_size),
+        },
+        _ => Layout {
+            band_height,
+            margin,
+            text_width,
+            qr_size: None,
+        },
+    }
+}
+
+/// Shrinks the text size until the text fits in `max_width`.
+fn fit_scale(font: &FontVec, text: &str, size: f32, max_width: u32) -> PxScale {
+    let scale = PxScale::from(size);
+    let (text_width, _) = text_size(scale, font, text);
+    if text_width > max_width {
+        PxScale::from(size * max_width as f32 / text_width as f32)
+    } else {
+        scale
+    }
+}
+
+fn draw_qr(card: &mut RgbaImage, url: &str, x: u32, y: u32, size: u32) -> anyhow::Result<()> {
+    let qr = QRBuilder::new(url).ecl(ECL::L).build()?;
+    draw_filled_rect_mut(
+        card,
+        Rect::at(x as i32, y as i32).of_size(size, size),
+        WHITE,
+    );
+    // Leave a quiet zone of two modules around the code.
+    let module = (size / (qr.size as u32 + 4)).max(1);
+    let offset = size.saturating_sub(module * qr.size as u32) / 2;
+    for row in 0..qr.size {
+        for column in 0..qr.size {
+            if qr[row][column].value() {
+                let rect = Rect::at(
+                    (x + offset + column as u32 * module) as i32,
+                    (y + offset + row as u32 * module) as i32,
+                )
+                .of_size(module, module);
+                draw_filled_rect_mut(card, rect, BLACK);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_layout_has_qr_code() {
+        let CardOptions { width, height, .. } = CardOptions::default();
+        assert_eq!(
+            layout(width, height, true),
+            Layout {
+                band_height: 210,
+                margin: 31,
+                text_width: 928,
+                qr_size: Some(179),
+            }
+        );
+    }
+
+    #[test]
+    fn narrow_layout_leaves_out_qr_code() {
+        let CardOptions { width, height, .. } = CardOptions {
+            width: 200,
+            height: 2160,
+            qr: true,
+        }
+        .clamped();
+        let layout = layout(width, height, true);
+        assert_eq!(layout.qr_size, None);
+        assert_eq!(layout.text_width, 0);
+    }
+}
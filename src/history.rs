@@ -0,0 +1,144 @@
+use std::{collections::VecDeque, fmt::Write};
+
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use serde::Serialize;
+
+use crate::{
+    id::{RoomId, WorldId},
+    metrics::{self, Histogram, WORLD_DURATION_BUCKETS},
+    Location,
+};
+
+/// The number of visits kept in the session history.
+const MAX_VISITS: usize = 1000;
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Visit {
+    pub joined_at: DateTime<Local>,
+    pub left_at: Option<DateTime<Local>>,
+    pub world_id: WorldId,
+    /// The room with the configured attributes redacted.
+    pub room_id: Option<RoomId>,
+    pub world_name: Option<String>,
+    pub author_name: Option<String>,
+    pub instance_type: Option<&'static str>,
+}
+
+pub struct History {
+    visits: VecDeque<Visit>,
+    /// How long each visit lasted, including visits that no longer fit in `visits`.
+    world_durations: Histogram<{ WORLD_DURATION_BUCKETS.len() }>,
+    /// The number of rooms joined since where-am-i started.
+    joins: u64,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            visits: VecDeque::new(),
+            world_durations: Histogram::new(WORLD_DURATION_BUCKETS),
+            joins: 0,
+        }
+    }
+}
+
+/// Converts a log timestamp to a time zone aware time.
+pub fn local_time(timestamp: NaiveDateTime) -> DateTime<Local> {
+    Local
+        .from_local_datetime(&timestamp)
+        .earliest()
+        .unwrap_or_else(|| timestamp.and_utc().with_timezone(&Local))
+}
+
+impl History {
+    pub fn visits(&self) -> &VecDeque<Visit> {
+        &self.visits
+    }
+
+    pub fn join(&mut self, timestamp: NaiveDateTime, location: &Location) {
+        let Some(world_id) = location.world_id else {
+            return;
+        };
+        self.leave(timestamp);
+        self.joins += 1;
+        if self.visits.len() == MAX_VISITS {
+            self.visits.pop_front();
+        }
+        let world = location.world.as_ref();
+        self.visits.push_back(Visit {
+            joined_at: local_time(timestamp),
+            left_at: None,
+            world_id,
+            room_id: location.room_id.clone(),
+            world_name: world.and_then(|world| world.name.clone()),
+            author_name: world.and_then(|world| world.author_name.clone()),
+            instance_type: location
+                .full_room_id
+                .as_ref()
+                .map(|room| room.instance.access().as_str()),
+        });
+    }
+
+    /// Forgets the visits from a previous VRChat session. Their durations are still counted.
+    pub fn start_session(&mut self) {
+        self.visits.clear();
+    }
+
+    pub fn leave(&mut self, timestamp: NaiveDateTime) {
+        if let Some(visit) = self.visits.back_mut() {
+            if visit.left_at.is_none() {
+                let left_at = local_time(timestamp);
+                visit.left_at = Some(left_at);
+                let seconds = (left_at - visit.joined_at).num_milliseconds() as f64 / 1000.0;
+                self.world_durations.observe(seconds.max(0.0));
+            }
+        }
+    }
+
+    pub fn to_metrics(&self) -> String {
+        let mut metrics = String::new();
+        self.world_durations.write(
+            &mut metrics,
+            "where_am_i_world_duration_seconds",
+            "How long each world visit lasted.",
+        );
+        metrics::write_counter(
+            &mut metrics,
+            "where_am_i_room_joins_total",
+            "Rooms joined according to the log.",
+            self.joins,
+        );
+        metrics
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("joined_at,left_at,world_id,world_name,author_name,instance_type\r\n");
+        for visit in &self.visits {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{}\r",
+                visit.joined_at.to_rfc3339(),
+                visit
+                    .left_at
+                    .map(|left_at| left_at.to_rfc3339())
+                    .unwrap_or_default(),
+                visit.world_id,
+                csv_field(visit.world_name.as_deref().unwrap_or_default()),
+                csv_field(visit.author_name.as_deref().unwrap_or_default()),
+                visit.instance_type.unwrap_or_default(),
+            )
+            .unwrap();
+        }
+        csv
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
@@ -0,0 +1,424 @@
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use anyhow::{anyhow, Context};
+use serde::{de::Error, Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Removes the type prefix from an ID, also accepting a bare UUID without one.
+fn strip_id_prefix<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    match s.strip_prefix(prefix) {
+        Some(uuid) => Some(uuid),
+        // Anything else with an underscore is an ID of a different type.
+        None if s.contains('_') => None,
+        None => Some(s),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WorldId(Uuid);
+
+impl fmt::Display for WorldId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "wrld_{}", self.0.as_hyphenated())
+    }
+}
+
+impl FromStr for WorldId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uuid = strip_id_prefix(s, "wrld_")
+            .ok_or_else(|| anyhow!("world ID does not begin with wrld"))?;
+        Ok(WorldId(uuid.parse()?))
+    }
+}
+
+impl Serialize for WorldId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for WorldId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <Cow<'de, str>>::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UserId(Uuid);
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "usr_{}", self.0.as_hyphenated())
+    }
+}
+
+impl FromStr for UserId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let uuid =
+            strip_id_prefix(s, "usr_").ok_or_else(|| anyhow!("user ID does not begin with usr"))?;
+        Ok(UserId(uuid.parse()?))
+    }
+}
+
+impl<'de> Deserialize<'de> for UserId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <Cow<'de, str>>::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+impl Serialize for UserId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstanceId {
+    pub id: u32,
+    pub attributes: Vec<(String, String)>,
+}
+
+impl InstanceId {
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Who can join the instance.
+    pub fn access(&self) -> InstanceAccess {
+        if self.attribute("group").is_some() {
+            match self.attribute("groupAccessType") {
+                Some("public") => InstanceAccess::GroupPublic,
+                Some("plus") => InstanceAccess::GroupPlus,
+                _ => InstanceAccess::Group,
+            }
+        } else if self.attribute("hidden").is_some() {
+            InstanceAccess::FriendsPlus
+        } else if self.attribute("friends").is_some() {
+            InstanceAccess::Friends
+        } else if self.attribute("private").is_some() {
+            if self.attribute("canRequestInvite").is_some() {
+                InstanceAccess::InvitePlus
+            } else {
+                InstanceAccess::Invite
+            }
+        } else {
+            InstanceAccess::Public
+        }
+    }
+
+    /// The region code of the server hosting the instance, like "us" or "jp".
+    pub fn region(&self) -> &str {
+        // Instances without a region attribute are hosted in the US.
+        self.attribute("region").unwrap_or("us")
+    }
+
+    /// The name VRChat uses for the instance's region, if it is a known one.
+    pub fn region_name(&self) -> Option<&'static str> {
+        match self.region() {
+            "us" => Some("US West"),
+            "use" => Some("US East"),
+            "eu" => Some("Europe"),
+            "jp" => Some("Japan"),
+            _ => None,
+        }
+    }
+
+    /// The user who created a friends or invite instance.
+    pub fn owner(&self) -> Option<UserId> {
+        ["hidden", "friends", "private"]
+            .iter()
+            .find_map(|key| self.attribute(key)?.parse().ok())
+    }
+
+    /// The group roles allowed to join a group instance, if it is restricted to some roles.
+    pub fn role_ids(&self) -> Vec<&str> {
+        self.attribute("roleIds")
+            .map(|ids| ids.split(',').filter(|id| !id.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    /// The instance ID with only the attributes that the website's launch page understands.
+    pub fn launch_id(&self) -> Self {
+        Self {
+            id: self.id,
+            attributes: self
+                .attributes
+                .iter()
+                .filter(|(k, _)| LAUNCH_ATTRIBUTES.contains(&k.as_str()))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    pub fn redact(&self, keys: &[String]) -> Self {
+        Self {
+            id: self.id,
+            attributes: self
+                .attributes
+                .iter()
+                .filter(|(k, _)| !keys.contains(k))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// The attributes that make up an instance's identity, which launch links must keep. VRChat
+/// opens a different instance when any of these are left out, and doesn't accept others.
+const LAUNCH_ATTRIBUTES: &[&str] = &[
+    "hidden",
+    "friends",
+    "private",
+    "canRequestInvite",
+    "group",
+    "groupAccessType",
+    "roleIds",
+    "strict",
+    "ageGate",
+    "region",
+    "nonce",
+];
+
+/// Who can join an instance, serialized with the names from the VRChat website.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum InstanceAccess {
+    #[serde(rename = "public")]
+    Public,
+    #[serde(rename = "friends+")]
+    FriendsPlus,
+    #[serde(rename = "friends")]
+    Friends,
+    #[serde(rename = "invite+")]
+    InvitePlus,
+    #[serde(rename = "invite")]
+    Invite,
+    #[serde(rename = "group")]
+    Group,
+    #[serde(rename = "group+")]
+    GroupPlus,
+    #[serde(rename = "groupPublic")]
+    GroupPublic,
+}
+
+impl InstanceAccess {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Public => "public",
+            Self::FriendsPlus => "friends+",
+            Self::Friends => "friends",
+            Self::InvitePlus => "invite+",
+            Self::Invite => "invite",
+            Self::Group => "group",
+            Self::GroupPlus => "group+",
+            Self::GroupPublic => "groupPublic",
+        }
+    }
+}
+
+impl fmt::Display for InstanceAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for InstanceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.id)?;
+        for (k, v) in &self.attributes {
+            if v.is_empty() {
+                write!(f, "~{k}")?;
+            } else {
+                write!(f, "~{k}({v})")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits an instance ID on the `~` characters that are not inside an attribute value.
+fn split_attributes(s: &str) -> impl Iterator<Item = &str> {
+    let mut depth = 0usize;
+    let mut start = 0;
+    let mut parts = Vec::new();
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '~' if depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&s[start..]);
+    parts.into_iter()
+}
+
+impl FromStr for InstanceId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut attributes = split_attributes(s);
+        let id = attributes.next().unwrap();
+        let attributes = attributes
+            .map(|a| {
+                // Flags like canRequestInvite have no value.
+                let (key, value) = match a.split_once('(') {
+                    Some((key, rest)) => (
+                        key,
+                        rest.strip_suffix(')').context("invalid attribute value")?,
+                    ),
+                    None => (a, ""),
+                };
+                anyhow::ensure!(!key.is_empty(), "invalid attribute");
+                Ok((key.to_owned(), value.to_owned()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>();
+        Ok(Self {
+            id: id.parse().context("invalid instance id")?,
+            attributes: attributes.context("invalid attributes")?,
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for InstanceId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <Cow<'de, str>>::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomId {
+    pub world: WorldId,
+    pub instance: InstanceId,
+}
+
+impl RoomId {
+    pub fn redact(&self, keys: &[String]) -> Self {
+        Self {
+            world: self.world,
+            instance: self.instance.redact(keys),
+        }
+    }
+}
+
+impl fmt::Display for RoomId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.world, self.instance)
+    }
+}
+
+impl FromStr for RoomId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (world, instance) = s.split_once(':').context("invalid room ID")?;
+        Ok(RoomId {
+            world: world.parse().context("invalid world ID")?,
+            instance: instance.parse().context("invalid instance ID")?,
+        })
+    }
+}
+
+impl Serialize for RoomId {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for RoomId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = <Cow<'de, str>>::deserialize(deserializer)?;
+        FromStr::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROOM: &str = "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~private(usr_c1644b5b-3ca4-45b4-97c6-a2a0de70d469)~canRequestInvite~region(eu)~nonce(c3f8b9e2-1d4a-4f6b-9a7e-2b5c8d0e1f3a)";
+
+    const UUID: &str = "4432ea9b-729c-46e3-8eaf-846aa0a37fdd";
+
+    #[test]
+    fn prefixed_ids() {
+        let world: WorldId = format!("wrld_{UUID}").parse().unwrap();
+        assert_eq!(world.to_string(), format!("wrld_{UUID}"));
+        let user: UserId = format!("usr_{UUID}").parse().unwrap();
+        assert_eq!(user.to_string(), format!("usr_{UUID}"));
+    }
+
+    #[test]
+    fn bare_uuid_ids() {
+        let world: WorldId = UUID.parse().unwrap();
+        assert_eq!(world.to_string(), format!("wrld_{UUID}"));
+        let user: UserId = UUID.parse().unwrap();
+        assert_eq!(user.to_string(), format!("usr_{UUID}"));
+    }
+
+    #[test]
+    fn wrong_prefix_ids() {
+        assert!(format!("usr_{UUID}").parse::<WorldId>().is_err());
+        assert!(format!("wrld_{UUID}").parse::<UserId>().is_err());
+        assert!(format!("usr_{UUID}:12345").parse::<RoomId>().is_err());
+    }
+
+    #[test]
+    fn colons_in_attribute_values() {
+        let id = "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(use:2001:db8::1)~debugInfo(a:b)";
+        let room: RoomId = id.parse().unwrap();
+        assert_eq!(
+            room.world.to_string(),
+            "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd"
+        );
+        assert_eq!(room.instance.region(), "use:2001:db8::1");
+        assert_eq!(room.instance.attribute("debugInfo"), Some("a:b"));
+        assert_eq!(room.to_string(), id);
+    }
+
+    #[test]
+    fn redacted_room_has_no_listed_attributes() {
+        let room: RoomId = ROOM.parse().unwrap();
+        let redacted = room.redact(&["private".into(), "nonce".into()]).to_string();
+        assert!(!redacted.contains("private"));
+        assert!(!redacted.contains("usr_"));
+        assert!(!redacted.contains("nonce"));
+        assert_eq!(
+            redacted,
+            "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~canRequestInvite~region(eu)"
+        );
+    }
+}
@@ -1,51 +1,224 @@
 use std::{
     ffi::OsStr,
-    io,
+    future::Future,
+    io::{self, Read, SeekFrom},
     path::{Path, PathBuf},
     pin::Pin,
-    str,
-    task::{self, Poll},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{self, ready, Poll},
     time::Duration,
 };
 
 use anyhow::Context;
 use async_stream::try_stream;
 use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
-use futures::{channel::mpsc, Stream, TryStream, TryStreamExt};
-use notify::{event::CreateKind, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use encoding_rs::Encoding;
+use flate2::read::MultiGzDecoder;
+use futures::{channel::mpsc, future, Stream, StreamExt, TryStream, TryStreamExt};
+use notify::{
+    event::CreateKind, EventKind, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher,
+};
 use pin_project_lite::pin_project;
+use serde::Serialize;
 use tokio::{
     fs::File,
-    io::{AsyncBufReadExt, AsyncRead, BufReader, ReadBuf},
-    time::{interval, Interval, MissedTickBehavior},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, ReadBuf},
+    sync::watch,
+    time::{interval, sleep, timeout, Interval, MissedTickBehavior},
 };
-use tracing::debug;
+use tracing::{debug, warn};
+#[cfg(windows)]
 use windows::Storage::UserDataPaths;
 
-use crate::RoomId;
+use crate::metrics;
+
+mod parse;
+
+use parse::parse_line;
+pub use parse::{LogEvent, LogEventKind};
+
+/// The separator between log entries.
+const END_STR: &str = "\n\n\r\n";
 
 pub fn autodetect_path() -> anyhow::Result<PathBuf> {
+    if let Some(path) = std::env::var_os("VRCHAT_LOG_DIR") {
+        let path = PathBuf::from(path);
+        debug!(?path, "Using VRChat log directory from VRCHAT_LOG_DIR");
+        return Ok(path);
+    }
+
+    let path = vrchat_data_path()?;
+
+    debug!(?path, "Found VRChat log directory");
+
+    Ok(path)
+}
+
+#[cfg(windows)]
+fn vrchat_data_path() -> anyhow::Result<PathBuf> {
     let paths = UserDataPaths::GetDefault().context("UserDataPath error")?;
     let hstring = paths
         .LocalAppDataLow()
         .context("UserDataPath::LocalAppDataLow error")?;
     let mut path = PathBuf::from(hstring.to_os_string());
     path.push("VRChat\\VRChat");
+    Ok(path)
+}
 
-    debug!(?path, "Found VRChat log directory");
+#[cfg(not(windows))]
+fn vrchat_data_path() -> anyhow::Result<PathBuf> {
+    let home = std::env::var_os("HOME").context("HOME is not set")?;
+    Ok(wine_data_path(Path::new(&home)))
+}
 
-    Ok(path)
+/// Looks for VRChat's data directory inside the Wine prefixes under `home` that VRChat is usually
+/// run in. If none has it yet, the usual one for the platform is returned, so that VRChat can
+/// create it.
+#[cfg(not(windows))]
+fn wine_data_path(home: &Path) -> PathBuf {
+    let candidates = [
+        // Proton, on Linux and the Steam Deck. 438100 is VRChat's Steam app ID.
+        home.join(".steam/steam/steamapps/compatdata/438100/pfx/drive_c/users/steamuser"),
+        home.join(".local/share/Steam/steamapps/compatdata/438100/pfx/drive_c/users/steamuser"),
+        // CrossOver, on macOS.
+        home.join("Library/Application Support/CrossOver/Bottles/Steam/drive_c/users/crossover"),
+    ];
+    let paths: Vec<_> = candidates
+        .into_iter()
+        .map(|user| user.join("AppData/LocalLow/VRChat/VRChat"))
+        .collect();
+    let usual = if cfg!(target_os = "macos") {
+        paths.len() - 1
+    } else {
+        0
+    };
+    paths
+        .iter()
+        .find(|path| path.is_dir())
+        .unwrap_or(&paths[usual])
+        .clone()
+}
+
+/// Settings for [`log_events`].
+pub struct LogOptions {
+    /// When nonzero, only this many bytes at the end of the log file that is active at startup
+    /// are read. Log files created later are read in full.
+    pub tail_bytes: u64,
+    /// Sends every event from the log file that is active at startup. Otherwise only the events
+    /// that make up the current state are sent for the lines already in the file.
+    pub replay: bool,
+    /// Reading the directory at startup is retried up to this many times, waiting longer after
+    /// each failure, in case something else has it locked for a moment.
+    pub read_retries: u32,
+    pub encoding: &'static Encoding,
+    /// How often to look for new log files, instead of waiting for the system to report them.
+    pub poll_interval: Option<Duration>,
+    /// How often to check for new lines at the end of the log file. Must not be zero.
+    pub tail_poll_interval: Duration,
+    /// Updated whenever reading moves on to a newer log file.
+    pub current_file: watch::Sender<Option<LogFileInfo>>,
+    pub line_counts: Arc<LineCounts>,
+    /// Sends lines that weren't understood as [`LogEventKind::Unrecognized`] events.
+    pub keep_unrecognized: bool,
+}
+
+/// How many log lines have been read, and whether they were understood.
+#[derive(Default)]
+pub struct LineCounts {
+    read: AtomicU64,
+    parsed: AtomicU64,
+    skipped: AtomicU64,
+}
+
+impl LineCounts {
+    fn count(&self, parsed: bool) {
+        self.read.fetch_add(1, Ordering::Relaxed);
+        if parsed {
+            self.parsed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.skipped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// The number of lines that were understood.
+    pub fn parsed(&self) -> u64 {
+        self.parsed.load(Ordering::Relaxed)
+    }
+
+    pub fn write_metrics(&self, out: &mut String) {
+        metrics::write_counter(
+            out,
+            "where_am_i_lines_read_total",
+            "Log lines read.",
+            self.read.load(Ordering::Relaxed),
+        );
+        metrics::write_counter(
+            out,
+            "where_am_i_lines_parsed_total",
+            "Log lines that were understood.",
+            self.parsed.load(Ordering::Relaxed),
+        );
+        metrics::write_counter(
+            out,
+            "where_am_i_lines_skipped_total",
+            "Log lines that were not understood or were cut off at the start of the tail.",
+            self.skipped.load(Ordering::Relaxed),
+        );
+    }
+}
+
+/// The log file that events are currently being read from.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFileInfo {
+    pub file_name: String,
+    pub timestamp: NaiveDateTime,
 }
 
-pub fn log_events(path: impl AsRef<Path>) -> impl Stream<Item = anyhow::Result<LogEvent>> {
-    let latest_file = log_files(path);
-    Switch::new(latest_file.map_ok(|file| file_log_events(file.path)))
+/// Streams events from the newest log file.
+pub fn log_events(
+    path: impl AsRef<Path>,
+    options: LogOptions,
+) -> impl Stream<Item = anyhow::Result<LogEvent>> {
+    let latest_file = log_files(path, options.read_retries, options.poll_interval);
+    let mut tail_bytes = Some(options.tail_bytes);
+    let mut catch_up = Some(!options.replay);
+    let encoding = options.encoding;
+    let current_file = options.current_file;
+    let line_counts = options.line_counts;
+    let tail_poll_interval = options.tail_poll_interval;
+    let keep_unrecognized = options.keep_unrecognized;
+    Switch::new(latest_file.map_ok(move |file| {
+        debug!(path = ?file.path, "Reading log file");
+        current_file.send_replace(Some(LogFileInfo {
+            file_name: file
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned(),
+            timestamp: file.timestamp,
+        }));
+        file_log_events(
+            file.path,
+            tail_bytes.take().unwrap_or_default(),
+            catch_up.take().unwrap_or_default(),
+            encoding,
+            line_counts.clone(),
+            tail_poll_interval,
+            keep_unrecognized,
+        )
+    }))
 }
 
 fn parse_log_file_name(name: &OsStr) -> Option<NaiveDateTime> {
     let name = name.to_str()?;
+    let name = name.strip_suffix(".gz").unwrap_or(name);
     let timestamp = name.strip_prefix("output_log_")?.strip_suffix(".txt")?;
-    if timestamp.len() != 19 || timestamp.chars().any(|c| !c.is_ascii()) {
+    if timestamp.len() != 19 || !timestamp.is_ascii() {
         return None;
     }
     let bytes = timestamp.as_bytes();
@@ -70,71 +243,229 @@ fn parse_log_file_name(name: &OsStr) -> Option<NaiveDateTime> {
     Some(NaiveDateTime::new(date, time))
 }
 
+/// Lists every log file in a directory, oldest first.
+pub async fn archived_log_files(path: impl AsRef<Path>) -> anyhow::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let mut files = Vec::new();
+    let mut reader = tokio::fs::read_dir(path)
+        .await
+        .context("log directory open error")?;
+    while let Some(entry) = reader
+        .next_entry()
+        .await
+        .context("log directory read error")?
+    {
+        let name = entry.file_name();
+        if let Some(timestamp) = parse_log_file_name(&name) {
+            files.push((timestamp, path.join(name)));
+        }
+    }
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Whether a log file was compressed with gzip to archive it.
+fn is_compressed(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("gz"))
+}
+
+/// Reads and decodes a whole log file, decompressing it if it is an archive.
+async fn read_log_text(path: &Path, encoding: &'static Encoding) -> anyhow::Result<String> {
+    let mut contents = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("log file read error: {}", path.display()))?;
+    if is_compressed(path) {
+        contents = tokio::task::spawn_blocking(move || {
+            let mut decompressed = Vec::new();
+            MultiGzDecoder::new(&contents[..]).read_to_end(&mut decompressed)?;
+            io::Result::Ok(decompressed)
+        })
+        .await?
+        .with_context(|| format!("log file decompression error: {}", path.display()))?;
+    }
+    let (text, _, _) = encoding.decode(&contents);
+    Ok(text.into_owned())
+}
+
+/// Parses a whole log file without waiting for more lines to be written.
+pub async fn read_log_file(
+    path: impl AsRef<Path>,
+    encoding: &'static Encoding,
+) -> anyhow::Result<Vec<LogEvent>> {
+    let text = read_log_text(path.as_ref(), encoding).await?;
+    Ok(text
+        .split(END_STR)
+        .filter_map(|line| parse_line(line, false))
+        .collect())
+}
+
 #[derive(Debug)]
 struct LogFile {
     path: PathBuf,
     timestamp: NaiveDateTime,
 }
 
-fn log_files(path: impl AsRef<Path>) -> impl Stream<Item = anyhow::Result<LogFile>> {
+/// Finds the newest log file in a directory.
+async fn latest_log_file(path: &Path) -> anyhow::Result<Option<LogFile>> {
+    let mut latest = None::<LogFile>;
+    let mut reader = tokio::fs::read_dir(path)
+        .await
+        .context("log directory open error")?;
+    while let Some(entry) = reader
+        .next_entry()
+        .await
+        .context("log directory read error")?
+    {
+        let name = entry.file_name();
+        let Some(timestamp) = parse_log_file_name(&name) else {
+            continue;
+        };
+
+        if !entry
+            .file_type()
+            .await
+            .is_ok_and(|file_type| file_type.is_file())
+        {
+            continue;
+        }
+        if latest.is_none()
+            || latest
+                .as_ref()
+                .is_some_and(|latest| latest.timestamp < timestamp)
+        {
+            latest = Some(LogFile {
+                path: path.join(name),
+                timestamp,
+            });
+        }
+    }
+    Ok(latest)
+}
+
+/// How often to check for a missing log directory when its parent doesn't report changes.
+const MISSING_DIRECTORY_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Waits for the log directory to exist. VRChat only creates it the first time it runs, which may
+/// be after where-am-i was started.
+async fn wait_for_directory(path: &Path) {
+    let exists = || async { tokio::fs::metadata(path).await.is_ok_and(|m| m.is_dir()) };
+    if exists().await {
+        return;
+    }
+    warn!(
+        ?path,
+        "Log directory doesn't exist yet, waiting for VRChat to create it"
+    );
+    loop {
+        // Watch the closest existing parent, which changes when the next level is created.
+        let (sender, mut receiver) = mpsc::unbounded::<()>();
+        let watcher = path
+            .ancestors()
+            .skip(1)
+            .find(|parent| parent.is_dir())
+            .map(|parent| {
+                let mut watcher = RecommendedWatcher::new(
+                    move |_| _ = sender.unbounded_send(()),
+                    notify::Config::default(),
+                )?;
+                watcher.watch(parent, RecursiveMode::NonRecursive)?;
+                anyhow::Ok(watcher)
+            })
+            .transpose();
+        if let Err(error) = &watcher {
+            debug!(
+                ?error,
+                "Log directory parent watcher error, polling instead"
+            );
+        }
+        _ = timeout(MISSING_DIRECTORY_POLL_INTERVAL, receiver.next()).await;
+        drop(watcher);
+        if exists().await {
+            debug!(?path, "Log directory was created");
+            return;
+        }
+    }
+}
+
+/// Retries reading the log directory, for example while another program has it locked. The
+/// wait between attempts doubles each time, starting at 2 seconds.
+async fn with_read_retries<T, F>(
+    read_retries: u32,
+    mut read: impl FnMut() -> F,
+) -> anyhow::Result<T>
+where
+    F: Future<Output = anyhow::Result<T>>,
+{
+    let mut retries = 0;
+    loop {
+        match read().await {
+            Ok(value) => return Ok(value),
+            Err(error) if retries < read_retries => {
+                retries += 1;
+                let delay = Duration::from_secs(1 << retries.min(5));
+                warn!(
+                    ?error,
+                    retries,
+                    ?delay,
+                    "Log directory read error, retrying"
+                );
+                sleep(delay).await;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn log_files(
+    path: impl AsRef<Path>,
+    read_retries: u32,
+    poll_interval: Option<Duration>,
+) -> impl Stream<Item = anyhow::Result<LogFile>> {
     try_stream! {
-        let path = path.as_ref();
+        wait_for_directory(path.as_ref()).await;
+        // Watching a symlink doesn't report changes in its target on every platform.
+        let path = with_read_retries(read_retries, || async {
+            tokio::fs::canonicalize(path.as_ref())
+                .await
+                .context("log directory resolve error")
+        })
+        .await?;
+        debug!(?path, "Watching log directory");
         let (events_sender, events_receiver) = mpsc::unbounded::<Result<LogFile, notify::Error>>();
-        let mut watcher = RecommendedWatcher::new(
-            move |res| {
-                let mut evt: notify::Event = match res {
-                    Ok(evt) => evt,
-                    Err(e) => {
-                        _ = events_sender.unbounded_send(Err(e));
-                        return;
-                    }
-                };
-                if !matches!(evt.kind, EventKind::Any | EventKind::Create(CreateKind::Any | CreateKind::File)) {
+        let handler = move |res: notify::Result<notify::Event>| {
+            let mut evt = match res {
+                Ok(evt) => evt,
+                Err(e) => {
+                    _ = events_sender.unbounded_send(Err(e));
                     return;
                 }
-                let Some(path) = evt.paths.pop() else {
-                    return;
-                };
-                let Some(timestamp) = path.file_name().and_then(parse_log_file_name) else {
-                    return;
-                };
-                _ = events_sender.unbounded_send(Ok(LogFile { path, timestamp }));
-            },
-            notify::Config::default(),
-        )?;
+            };
+            if !matches!(evt.kind, EventKind::Any | EventKind::Create(CreateKind::Any | CreateKind::File)) {
+                return;
+            }
+            let Some(path) = evt.paths.pop() else {
+                return;
+            };
+            let Some(timestamp) = path.file_name().and_then(parse_log_file_name) else {
+                return;
+            };
+            _ = events_sender.unbounded_send(Ok(LogFile { path, timestamp }));
+        };
+        let mut watcher: Box<dyn Watcher + Send> = match poll_interval {
+            // Some network and virtual machine file systems don't report new files reliably, so
+            // look for them periodically instead.
+            Some(interval) => Box::new(PollWatcher::new(
+                handler,
+                notify::Config::default().with_poll_interval(interval),
+            )?),
+            None => Box::new(RecommendedWatcher::new(handler, notify::Config::default())?),
+        };
 
         watcher
-            .watch(path, RecursiveMode::NonRecursive)
+            .watch(&path, RecursiveMode::NonRecursive)
             .context("Directory watcher initialization error")?;
 
-        let mut latest = None::<LogFile>;
-        let mut reader = tokio::fs::read_dir(&path)
-            .await
-            .context("log directory open error")?;
-        while let Some(entry) = reader
-            .next_entry()
-            .await
-            .context("log directory read error")?
-        {
-            let name = entry.file_name();
-            let Some(timestamp) = parse_log_file_name(&name) else {
-                continue;
-            };
-
-            if !entry
-                .file_type()
-                .await
-                .is_ok_and(|file_type| file_type.is_file()) {
-                    continue;
-                }
-            if latest.is_none()
-                || latest
-                    .as_ref()
-                    .is_some_and(|latest| latest.timestamp < timestamp)
-            {
-                latest = Some(LogFile { path: path.join(name), timestamp });
-            }
-        }
+        let latest = with_read_retries(read_retries, || latest_log_file(&path)).await?;
 
         let mut latest_ts;
         if let Some(latest) = latest {
@@ -158,25 +489,34 @@ fn log_files(path: impl AsRef<Path>) -> impl Stream<Item = anyhow::Result<LogFil
 }
 
 pin_project! {
+    /// Reads a file that is still being written to. At the end of the file, waits for the poll
+    /// interval before reporting that nothing was read, so the caller can check on the file.
     struct LogReader {
         #[pin]
         file: File,
         #[pin]
         interval: Interval,
+        waiting: bool,
     }
 }
 
 impl LogReader {
-    fn new(file: File) -> Self {
+    fn new(file: File, poll_interval: Duration) -> Self {
         Self {
             file,
             interval: {
-                let mut interval = interval(Duration::from_millis(100));
+                let mut interval = interval(poll_interval);
                 interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
                 interval
             },
+            waiting: false,
         }
     }
+
+    async fn rewind(&mut self) -> io::Result<()> {
+        self.file.rewind().await?;
+        Ok(())
+    }
 }
 
 impl AsyncRead for LogReader {
@@ -186,108 +526,156 @@ impl AsyncRead for LogReader {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let mut this = self.project();
-        let original_len = buf.filled().len();
-        loop {
-            match this.file.as_mut().poll_read(cx, buf) {
-                Poll::Ready(Ok(())) if buf.filled().len() == original_len => {
-                    this.interval.reset();
-                    if matches!(this.interval.poll_tick(cx), Poll::Pending) {
-                        break Poll::Pending;
-                    }
-                }
-                other => break other,
+        if !*this.waiting {
+            let original_len = buf.filled().len();
+            ready!(this.file.poll_read(cx, buf))?;
+            if buf.filled().len() != original_len {
+                return Poll::Ready(Ok(()));
             }
+            this.interval.as_mut().reset();
+            *this.waiting = true;
         }
+        ready!(this.interval.poll_tick(cx));
+        *this.waiting = false;
+        Poll::Ready(Ok(()))
     }
 }
 
-#[derive(Debug)]
-pub struct LogEvent {
-    pub kind: LogEventKind,
-}
-
-#[derive(Debug)]
-pub enum LogEventKind {
-    // Log        -  [Behaviour] Successfully left room
-    LeftRoom,
-    // Log        -  [Behaviour] Joining wrld_900dd077-1337-c0fe-babe-71de05ea12c4:46115~hidden(usr_38116327-5a34-4fd8-ace0-21c93fb3f163)
-    JoiningRoom(RoomId),
+/// The events from the lines already in a log file that matter for the current state.
+#[derive(Default)]
+struct CatchUp {
+    user: Option<LogEvent>,
+    activity: Option<LogEvent>,
+    /// The events since the last time a room was joined or left.
+    room: Vec<LogEvent>,
 }
 
-fn parse_line(line: &str) -> Option<LogEvent> {
-    const TS_LEN: usize = "YYYY.MM.DD HH.MM.SS ".len();
-    if line.len() < TS_LEN || !line.is_char_boundary(TS_LEN) {
-        return None;
-    }
-    let (ts, rest) = line.split_at(TS_LEN);
-    let ts_bytes = ts.as_bytes();
-    if !ts.is_ascii()
-        || ts_bytes[4] != b'.'
-        || ts_bytes[7] != b'.'
-        || ts_bytes[10] != b' '
-        || ts_bytes[13] != b':'
-        || ts_bytes[16] != b':'
-        || ts_bytes[19] != b' '
-    {
-        return None;
+impl CatchUp {
+    fn push(&mut self, event: LogEvent) {
+        match event.kind {
+            LogEventKind::Authenticated { .. } => self.user = Some(event),
+            LogEventKind::ApplicationPaused(_) => self.activity = Some(event),
+            // A leave is kept so a session that ended outside of a room clears the location.
+            LogEventKind::JoiningRoom(_) | LogEventKind::LeftRoom | LogEventKind::Kicked { .. } => {
+                self.room.clear();
+                self.room.push(event);
+            }
+            LogEventKind::Unrecognized(_) => {}
+            _ => self.room.push(event),
+        }
     }
 
-    let year = ts[0..4].parse().ok()?;
-    let month = ts[5..7].parse().ok()?;
-    let day = ts[8..10].parse().ok()?;
-    let hour = ts[11..13].parse().ok()?;
-    let min = ts[14..16].parse().ok()?;
-    let sec = ts[17..19].parse().ok()?;
-
-    NaiveDate::from_ymd_opt(year, month, day)?;
-    NaiveTime::from_hms_opt(hour, min, sec)?;
-
-    let (source, message) = rest.split_once("-  ")?;
-    let source = source.trim_end();
-
-    if source != "Log" {
-        return None;
+    fn into_events(self) -> impl Iterator<Item = LogEvent> {
+        self.user.into_iter().chain(self.activity).chain(self.room)
     }
-
-    let kind = if message == "[Behaviour] Successfully left room" {
-        LogEventKind::LeftRoom
-    } else if let Some(room) = message
-        .strip_prefix("[Behaviour] Joining ")
-        .and_then(|id| id.parse().ok())
-    {
-        LogEventKind::JoiningRoom(room)
-    } else {
-        return None;
-    };
-
-    Some(LogEvent { kind })
 }
 
-fn file_log_events(path: impl AsRef<Path>) -> impl Stream<Item = anyhow::Result<LogEvent>> {
+/// Streams the events from a log file, then keeps waiting for more lines. With `catch_up`, the
+/// lines already in the file are reduced to the events in [`CatchUp`].
+fn file_log_events(
+    path: impl AsRef<Path>,
+    tail_bytes: u64,
+    catch_up: bool,
+    encoding: &'static Encoding,
+    line_counts: Arc<LineCounts>,
+    poll_interval: Duration,
+    keep_unrecognized: bool,
+) -> impl Stream<Item = anyhow::Result<LogEvent>> {
     try_stream! {
         let path = path.as_ref();
         debug!(?path, "Reading log");
-        let mut file = BufReader::new(LogReader::new(File::open(path).await?));
+        if is_compressed(path) {
+            // Archived logs aren't written to anymore, so they are read once instead of tailed.
+            let text = read_log_text(path, encoding).await?;
+            let mut events = Vec::new();
+            let mut catch_up = catch_up.then(CatchUp::default);
+            for line in text.split(END_STR) {
+                let event = parse_line(line, keep_unrecognized);
+                line_counts.count(event.as_ref().is_some_and(|event| {
+                    !matches!(event.kind, LogEventKind::Unrecognized(_))
+                }));
+                match (event, &mut catch_up) {
+                    (Some(event), Some(catch_up)) => catch_up.push(event),
+                    (Some(event), None) => events.push(event),
+                    (None, _) => {}
+                }
+            }
+            events.extend(catch_up.into_iter().flat_map(CatchUp::into_events));
+            for event in events {
+                yield event;
+            }
+            // Ending the stream would end all events, so wait for a newer log file instead.
+            future::pending::<()>().await;
+        }
+        let mut file = File::open(path).await?;
+        let len = file.metadata().await?.len();
+        let start = if tail_bytes != 0 && len > tail_bytes {
+            // Keep UTF-16 code units aligned.
+            file.seek(SeekFrom::Start((len - tail_bytes) & !1)).await?
+        } else {
+            0
+        };
+        let mut file = LogReader::new(file, poll_interval);
+        let mut decoder = encoding.new_decoder();
+        let mut position = start;
+        let mut catch_up = (catch_up && start < len).then(CatchUp::default);
 
-        let mut buffer = Vec::new();
+        let mut bytes = vec![0; 8192];
+        let mut text = String::new();
 
-        const END: &[u8; 4] = b"\n\n\r\n";
+        // After seeking into the middle of the file, skip ahead to the start of the next line.
+        let mut partial = start != 0;
 
         loop {
-            buffer.clear();
-            loop {
-                file.read_until(END[END.len() - 1], &mut buffer).await?;
-                if buffer.ends_with(END) {
-                    break;
+            let read = file.read(&mut bytes).await?;
+            if read == 0 {
+                // If the file is now shorter than what has already been read, it was truncated
+                // and is being rewritten from the start.
+                if tokio::fs::metadata(path).await.is_ok_and(|m| m.len() < position) {
+                    debug!(?path, "Log file truncated");
+                    file.rewind().await?;
+                    position = 0;
+                    decoder = encoding.new_decoder();
+                    text.clear();
+                    partial = false;
                 }
-            }
-            buffer.truncate(buffer.len() - END.len());
-            let Ok(line) = str::from_utf8(&buffer) else {
                 continue;
-            };
-
-            if let Some(event) = parse_line(line) {
-                yield event;
+            }
+            position += read as u64;
+            if let Some(needed) = decoder.max_utf8_buffer_length(read) {
+                text.reserve(needed);
+            }
+            let _ = decoder.decode_to_string(&bytes[..read], &mut text, false);
+
+            let mut consumed = 0;
+            while let Some(end) = text[consumed..].find(END_STR) {
+                let line = &text[consumed..consumed + end];
+                consumed += end + END_STR.len();
+                if partial {
+                    partial = false;
+                    line_counts.count(false);
+                    continue;
+                }
+                let event = parse_line(line, keep_unrecognized);
+                line_counts.count(event.as_ref().is_some_and(|event| {
+                    !matches!(event.kind, LogEventKind::Unrecognized(_))
+                }));
+                if let Some(event) = event {
+                    if let Some(catch_up) = &mut catch_up {
+                        catch_up.push(event);
+                    } else {
+                        yield event;
+                    }
+                }
+            }
+            text.drain(..consumed);
+            if position >= len {
+                if let Some(catch_up) = catch_up.take() {
+                    debug!(?path, "Caught up with the existing log lines");
+                    for event in catch_up.into_events() {
+                        yield event;
+                    }
+                }
             }
         }
     }
@@ -340,3 +728,208 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use futures::pin_mut;
+
+    use super::*;
+
+    const LEFT: &str = "2024.01.02 03:04:05 Log        -  [Behaviour] Successfully left room";
+    const JOINING: &str = "2024.01.02 03:04:06 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345";
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("where-am-i-{name}-{}.txt", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        path
+    }
+
+    fn append(path: &Path, line: &str) {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        write!(file, "{line}{END_STR}").unwrap();
+    }
+
+    async fn next_kind(
+        events: &mut (impl Stream<Item = anyhow::Result<LogEvent>> + Unpin),
+    ) -> LogEventKind {
+        timeout(Duration::from_secs(5), events.next())
+            .await
+            .expect("no event")
+            .unwrap()
+            .unwrap()
+            .kind
+    }
+
+    fn tail(
+        path: PathBuf,
+        encoding: &'static Encoding,
+    ) -> impl Stream<Item = anyhow::Result<LogEvent>> {
+        file_log_events(
+            path,
+            0,
+            false,
+            encoding,
+            Arc::default(),
+            Duration::from_millis(10),
+            false,
+        )
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn finds_new_files_through_symlink() {
+        let dir = std::env::temp_dir().join(format!("where-am-i-symlink-{}", std::process::id()));
+        let target = dir.join("VRChat");
+        let link = dir.join("logs");
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&target).unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        std::fs::write(target.join("output_log_2024-05-01_20-13-05.txt"), "").unwrap();
+
+        let files = log_files(&link, 0, None);
+        pin_mut!(files);
+        let next_name = |file: Option<anyhow::Result<LogFile>>| {
+            file.expect("no log file")
+                .unwrap()
+                .path
+                .file_name()
+                .unwrap()
+                .to_owned()
+        };
+        let first = timeout(Duration::from_secs(5), files.next()).await.unwrap();
+        assert_eq!(next_name(first), "output_log_2024-05-01_20-13-05.txt");
+
+        std::fs::write(target.join("output_log_2024-05-02_18-00-00.txt"), "").unwrap();
+        let second = timeout(Duration::from_secs(5), files.next()).await.unwrap();
+        assert_eq!(next_name(second), "output_log_2024-05-02_18-00-00.txt");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    #[test]
+    fn data_path_before_first_run() {
+        let home = std::env::temp_dir().join(format!("where-am-i-home-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&home);
+        std::fs::create_dir_all(&home).unwrap();
+
+        let proton = home.join(".steam/steam/steamapps/compatdata/438100/pfx/drive_c/users/steamuser/AppData/LocalLow/VRChat/VRChat");
+        assert_eq!(wine_data_path(&home), proton);
+
+        let steam_root = home.join(".local/share/Steam/steamapps/compatdata/438100/pfx/drive_c/users/steamuser/AppData/LocalLow/VRChat/VRChat");
+        std::fs::create_dir_all(&steam_root).unwrap();
+        assert_eq!(wine_data_path(&home), steam_root);
+
+        std::fs::remove_dir_all(home).unwrap();
+    }
+
+    #[tokio::test]
+    async fn polls_for_new_files() {
+        let dir = std::env::temp_dir().join(format!("where-am-i-poll-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let files = log_files(dir.clone(), 0, Some(Duration::from_millis(20)));
+        pin_mut!(files);
+        // Let the watcher take its first look at the directory.
+        let first = timeout(Duration::from_millis(100), files.next()).await;
+        assert!(first.is_err());
+
+        std::fs::write(dir.join("output_log_2024-05-02_18-00-00.txt"), "").unwrap();
+        let found = timeout(Duration::from_secs(5), files.next())
+            .await
+            .unwrap()
+            .expect("no log file")
+            .unwrap();
+        assert_eq!(
+            found.path.file_name().unwrap(),
+            "output_log_2024-05-02_18-00-00.txt"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn retries_failed_reads() {
+        let mut attempts = 0;
+        let result = with_read_retries(1, || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                anyhow::ensure!(attempt > 1, "locked");
+                Ok(attempt)
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 2);
+
+        let missing = std::env::temp_dir().join("where-am-i-missing-directory");
+        let result = with_read_retries(0, || async {
+            anyhow::Ok(tokio::fs::canonicalize(&missing).await?)
+        })
+        .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_appended_lines() {
+        let path = temp_log("append");
+        append(&path, LEFT);
+        let events = tail(path.clone(), encoding_rs::UTF_8);
+        pin_mut!(events);
+        assert!(matches!(
+            next_kind(&mut events).await,
+            LogEventKind::LeftRoom
+        ));
+        append(&path, JOINING);
+        assert!(matches!(
+            next_kind(&mut events).await,
+            LogEventKind::JoiningRoom(_)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn decodes_shift_jis() {
+        let path = temp_log("shift-jis");
+        let line = format!(
+            "2024.01.02 03:04:06 Log        -  [Behaviour] Joining or Creating Room: 黒猫の喫茶店{END_STR}"
+        );
+        let (bytes, _, unmappable) = encoding_rs::SHIFT_JIS.encode(&line);
+        assert!(!unmappable);
+        std::fs::write(&path, bytes).unwrap();
+        let events = tail(path.clone(), encoding_rs::SHIFT_JIS);
+        pin_mut!(events);
+        assert!(matches!(
+            next_kind(&mut events).await,
+            LogEventKind::JoiningOrCreatingRoom { name } if name == "黒猫の喫茶店"
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rereads_truncated_file() {
+        let path = temp_log("truncate");
+        for _ in 0..3 {
+            append(&path, LEFT);
+        }
+        let events = tail(path.clone(), encoding_rs::UTF_8);
+        pin_mut!(events);
+        for _ in 0..3 {
+            assert!(matches!(
+                next_kind(&mut events).await,
+                LogEventKind::LeftRoom
+            ));
+        }
+        std::fs::write(&path, "").unwrap();
+        append(&path, JOINING);
+        assert!(matches!(
+            next_kind(&mut events).await,
+            LogEventKind::JoiningRoom(_)
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+}
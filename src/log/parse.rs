@@ -0,0 +1,391 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Serialize, Serializer};
+
+use crate::id::{RoomId, UserId};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LogEvent {
+    /// When the line was logged, in local time.
+    pub timestamp: NaiveDateTime,
+    pub kind: LogEventKind,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub enum LogEventKind {
+    // Log        -  [Behaviour] Successfully left room
+    LeftRoom,
+    // Log        -  [Behaviour] Joining wrld_900dd077-1337-c0fe-babe-71de05ea12c4:46115~hidden(usr_38116327-5a34-4fd8-ace0-21c93fb3f163)
+    JoiningRoom(RoomId),
+    // Log        -  [Behaviour] Joining or Creating Room: The Black Cat
+    // Lines that start with the room ID instead of the world name are reported as JoiningRoom.
+    JoiningOrCreatingRoom {
+        name: String,
+    },
+    // Log        -  [Behaviour] Finished entering world.
+    JoinedRoom,
+    // Log        -  OnApplicationPause(True)
+    // Log        -  OnApplicationFocus(False)
+    ApplicationPaused(bool),
+    // Error      -  [Behaviour] Failed to load world: ...
+    // Error      -  [AssetBundleDownloadManager] World download failed: ...
+    WorldLoadFailed,
+    // Log        -  User Authenticated: Name (usr_38116327-5a34-4fd8-ace0-21c93fb3f163)
+    // Sent with only the user ID as its data, as before the name was parsed.
+    #[serde(serialize_with = "serialize_authenticated")]
+    Authenticated {
+        name: String,
+        user: UserId,
+    },
+    // Log        -  [Behaviour] OnPlayerJoined Name (usr_38116327-5a34-4fd8-ace0-21c93fb3f163)
+    // Older builds leave out the user ID.
+    PlayerJoined {
+        name: String,
+        user: Option<UserId>,
+    },
+    // Log        -  [Behaviour] OnPlayerLeft Name (usr_38116327-5a34-4fd8-ace0-21c93fb3f163)
+    PlayerLeft {
+        name: String,
+        user: Option<UserId>,
+    },
+    // Log        -  [ModerationManager] You have been kicked from this instance by majority vote
+    // Log        -  [Behaviour] Received executive message: You have been kicked from the instance
+    // Log        -  [Behaviour] Received executive message: The instance has been closed.
+    Kicked {
+        reason: Option<String>,
+    },
+    // Any other line that begins with a timestamp.
+    Unrecognized(String),
+}
+
+fn serialize_authenticated<S>(_name: &str, user: &UserId, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    user.serialize(serializer)
+}
+
+fn parse_application_paused(message: &str) -> Option<bool> {
+    let (method, rest) = message.split_once('(')?;
+    let state = match rest.strip_suffix(')')? {
+        "True" | "true" => true,
+        "False" | "false" => false,
+        _ => return None,
+    };
+    match method.trim_end() {
+        "OnApplicationPause" => Some(state),
+        "OnApplicationFocus" => Some(!state),
+        _ => None,
+    }
+}
+
+/// The beginnings of the messages VRChat logs when the world of the joined instance can't be
+/// loaded.
+const WORLD_LOAD_FAILURES: &[&str] = &[
+    "[Behaviour] Failed to load world: ",
+    "[AssetBundleDownloadManager] World download failed: ",
+];
+
+fn is_world_load_failure(message: &str) -> bool {
+    WORLD_LOAD_FAILURES
+        .iter()
+        .any(|failure| message.starts_with(failure))
+}
+
+fn parse_authenticated(message: &str) -> Option<(String, UserId)> {
+    let message = message.strip_prefix("[Behaviour] ").unwrap_or(message);
+    let rest = message.strip_prefix("User Authenticated: ")?;
+    let (name, id) = rest.strip_suffix(')')?.rsplit_once(" (")?;
+    Some((name.to_owned(), id.parse().ok()?))
+}
+
+/// Parses the player after `OnPlayerJoined` or `OnPlayerLeft`.
+fn parse_player(player: &str) -> (String, Option<UserId>) {
+    if let Some((name, id)) = player
+        .strip_suffix(')')
+        .and_then(|player| player.rsplit_once(" ("))
+    {
+        if let Ok(id) = id.parse() {
+            return (name.to_owned(), Some(id));
+        }
+    }
+    (player.to_owned(), None)
+}
+
+fn parse_joining_or_creating(message: &str) -> Option<LogEventKind> {
+    let rest = message.strip_prefix("[Behaviour] Joining or Creating Room: ")?;
+    let room = rest.split_once(' ').map_or(rest, |(room, _)| room);
+    Some(match room.parse() {
+        Ok(room) => LogEventKind::JoiningRoom(room),
+        Err(_) => LogEventKind::JoiningOrCreatingRoom {
+            name: rest.to_owned(),
+        },
+    })
+}
+
+/// The messages VRChat shows when it removes the local player from an instance, by a kick or by
+/// closing the instance. Other executive and moderation messages, like warnings that the instance
+/// is about to close, are not exits.
+const EXIT_MESSAGES: &[&str] = &[
+    "You have been kicked from the instance by majority vote",
+    "You have been kicked from this instance by majority vote",
+    "You have been kicked from the instance",
+    "You have been kicked from this instance",
+    "The instance has been closed",
+    "This instance has been closed",
+];
+
+fn parse_kicked(message: &str) -> Option<Option<String>> {
+    let reason = message
+        .strip_prefix("[Behaviour] Received executive message: ")
+        .or_else(|| message.strip_prefix("[ModerationManager] "))?;
+    EXIT_MESSAGES
+        .contains(&reason.trim_end_matches('.'))
+        .then(|| Some(reason.to_owned()))
+}
+
+/// Parses one log entry. Lines that begin with a timestamp but aren't understood are only kept,
+/// as [`LogEventKind::Unrecognized`], with `keep_unrecognized`.
+pub fn parse_line(line: &str, keep_unrecognized: bool) -> Option<LogEvent> {
+    const TS_LEN: usize = "YYYY.MM.DD HH.MM.SS ".len();
+    if line.len() < TS_LEN || !line.is_char_boundary(TS_LEN) {
+        return None;
+    }
+    let (ts, rest) = line.split_at(TS_LEN);
+    let ts_bytes = ts.as_bytes();
+    if !ts.is_ascii()
+        || ts_bytes[4] != b'.'
+        || ts_bytes[7] != b'.'
+        || ts_bytes[10] != b' '
+        || ts_bytes[13] != b':'
+        || ts_bytes[16] != b':'
+        || ts_bytes[19] != b' '
+    {
+        return None;
+    }
+
+    let year = ts[0..4].parse().ok()?;
+    let month = ts[5..7].parse().ok()?;
+    let day = ts[8..10].parse().ok()?;
+    let hour = ts[11..13].parse().ok()?;
+    let min = ts[14..16].parse().ok()?;
+    let sec = ts[17..19].parse().ok()?;
+
+    let date = NaiveDate::from_ymd_opt(year, month, day)?;
+    let time = NaiveTime::from_hms_opt(hour, min, sec)?;
+    let timestamp = NaiveDateTime::new(date, time);
+
+    let (source, message) = rest.split_once("-  ")?;
+    let source = source.trim_end();
+    let unrecognized = || keep_unrecognized.then(|| LogEventKind::Unrecognized(line.to_owned()));
+
+    let kind = if !matches!(source, "Log" | "Debug" | "Error") {
+        unrecognized()?
+    } else if is_world_load_failure(message) {
+        LogEventKind::WorldLoadFailed
+    } else if source == "Error" {
+        // VRChat has logged the messages below at either Log or Debug level, depending on the
+        // version.
+        unrecognized()?
+    } else if message == "[Behaviour] Successfully left room" {
+        LogEventKind::LeftRoom
+    } else if let Some(kind) = parse_joining_or_creating(message) {
+        kind
+    } else if let Some(room) = message
+        .strip_prefix("[Behaviour] Joining ")
+        .and_then(|id| id.parse().ok())
+    {
+        LogEventKind::JoiningRoom(room)
+    } else if message == "[Behaviour] Finished entering world." {
+        LogEventKind::JoinedRoom
+    } else if let Some(paused) = parse_application_paused(message) {
+        LogEventKind::ApplicationPaused(paused)
+    } else if let Some((name, user)) = parse_authenticated(message) {
+        LogEventKind::Authenticated { name, user }
+    } else if let Some(player) = message.strip_prefix("[Behaviour] OnPlayerJoined ") {
+        let (name, user) = parse_player(player);
+        LogEventKind::PlayerJoined { name, user }
+    } else if let Some(player) = message.strip_prefix("[Behaviour] OnPlayerLeft ") {
+        let (name, user) = parse_player(player);
+        LogEventKind::PlayerLeft { name, user }
+    } else if let Some(reason) = parse_kicked(message) {
+        LogEventKind::Kicked { reason }
+    } else {
+        unrecognized()?
+    };
+
+    Some(LogEvent { timestamp, kind })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(message: &str) -> Option<LogEventKind> {
+        parse_line(
+            &format!("2024.05.01 20:13:05 Log        -  {message}"),
+            false,
+        )
+        .map(|event| event.kind)
+    }
+
+    fn kinds(snippet: &str) -> Vec<LogEventKind> {
+        snippet
+            .lines()
+            .filter_map(|line| parse_line(line.trim(), false))
+            .map(|event| event.kind)
+            .collect()
+    }
+
+    #[test]
+    fn authenticated_data_is_user_id() {
+        let event = parse_line(
+            "2024.05.01 20:13:05 Log        -  User Authenticated: Fiona (usr_38116327-5a34-4fd8-ace0-21c93fb3f163)",
+            false,
+        )
+        .unwrap();
+        assert!(matches!(
+            &event.kind,
+            LogEventKind::Authenticated { name, .. } if name == "Fiona"
+        ));
+        assert_eq!(
+            serde_json::to_value(&event.kind).unwrap(),
+            serde_json::json!({
+                "type": "authenticated",
+                "data": "usr_38116327-5a34-4fd8-ace0-21c93fb3f163",
+            })
+        );
+    }
+
+    #[test]
+    fn create_flow() {
+        let kinds = kinds(
+            "2024.05.01 20:13:05 Log        -  [Behaviour] Joining or Creating Room: The Black Cat
+             2024.05.01 20:13:05 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(eu)
+             2024.05.01 20:13:12 Log        -  [Behaviour] Finished entering world.",
+        );
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                LogEventKind::JoiningOrCreatingRoom { name },
+                LogEventKind::JoiningRoom(_),
+                LogEventKind::JoinedRoom,
+            ] if name == "The Black Cat"
+        ));
+    }
+
+    #[test]
+    fn join_flow() {
+        let kinds = kinds(
+            "2024.05.01 20:13:05 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(eu)
+             2024.05.01 20:13:05 Log        -  [Behaviour] Joining or Creating Room: The Black Cat
+             2024.05.01 20:13:12 Log        -  [Behaviour] Finished entering world.",
+        );
+        assert!(matches!(
+            kinds.as_slice(),
+            [
+                LogEventKind::JoiningRoom(_),
+                LogEventKind::JoiningOrCreatingRoom { .. },
+                LogEventKind::JoinedRoom,
+            ]
+        ));
+    }
+
+    #[test]
+    fn joining_or_creating_with_room_id() {
+        assert!(matches!(
+            kind("[Behaviour] Joining or Creating Room: wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(eu) The Black Cat"),
+            Some(LogEventKind::JoiningRoom(room)) if room.instance.region() == "eu"
+        ));
+    }
+
+    #[test]
+    fn log_and_debug_sources() {
+        for line in [
+            "2024.05.01 20:13:05 Log        -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(eu)",
+            "2021.08.14 21:02:11 Debug      -  [Behaviour] Joining wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~region(eu)",
+        ] {
+            assert!(matches!(
+                parse_line(line, false).map(|event| event.kind),
+                Some(LogEventKind::JoiningRoom(room)) if room.instance.region() == "eu"
+            ));
+        }
+        for line in [
+            "2024.05.01 20:14:40 Log        -  [Behaviour] Successfully left room",
+            "2021.08.14 21:05:02 Debug      -  [Behaviour] Successfully left room",
+        ] {
+            assert!(matches!(
+                parse_line(line, false).map(|event| event.kind),
+                Some(LogEventKind::LeftRoom)
+            ));
+        }
+        assert!(parse_line(
+            "2024.05.01 20:14:40 Warning    -  [Behaviour] Successfully left room",
+            false
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn world_load_failure() {
+        let failed = |line: &str| {
+            matches!(
+                parse_line(line, false).map(|event| event.kind),
+                Some(LogEventKind::WorldLoadFailed)
+            )
+        };
+        assert!(failed(
+            "2024.05.01 20:13:05 Error      -  [Behaviour] Failed to load world: AssetBundle is corrupt"
+        ));
+        assert!(failed(
+            "2024.05.01 20:13:05 Error      -  [AssetBundleDownloadManager] World download failed: 404"
+        ));
+        // Only the messages at the start of a line count, not ones quoted by other components.
+        assert!(!failed(
+            "2024.05.01 20:13:05 Log        -  [Chat] Failed to load world: AssetBundle is corrupt"
+        ));
+        assert!(!failed(
+            "2024.05.01 20:13:05 Warning    -  [Behaviour] Failed to load world: AssetBundle is corrupt"
+        ));
+    }
+
+    #[test]
+    fn kicked_by_vote() {
+        assert!(matches!(
+            kind("[ModerationManager] You have been kicked from this instance by majority vote"),
+            Some(LogEventKind::Kicked { reason: Some(reason) })
+                if reason == "You have been kicked from this instance by majority vote"
+        ));
+    }
+
+    #[test]
+    fn kicked_by_executive_message() {
+        assert!(matches!(
+            kind("[Behaviour] Received executive message: You have been kicked from the instance."),
+            Some(LogEventKind::Kicked { .. })
+        ));
+    }
+
+    #[test]
+    fn instance_closed_is_an_exit() {
+        assert!(matches!(
+            kind("[Behaviour] Received executive message: The instance has been closed."),
+            Some(LogEventKind::Kicked { reason: Some(reason) })
+                if reason == "The instance has been closed."
+        ));
+    }
+
+    #[test]
+    fn instance_closing_is_not_an_exit() {
+        assert!(kind(
+            "[Behaviour] Received executive message: This instance will close in 5 minutes"
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn other_moderation_messages_are_not_kicks() {
+        assert!(kind("[ModerationManager] Someone has been kicked").is_none());
+        assert!(kind("[ModerationManager] You have been warned").is_none());
+    }
+}
@@ -1,415 +1,3312 @@
 use std::{
     borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
     convert::Infallible,
-    fmt,
+    future::Future,
+    io,
     path::PathBuf,
-    str::{self, FromStr},
+    pin::pin,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use anyhow::{anyhow, Context};
-use api::{VrcApiClient, World};
+use ab_glyph::FontVec;
+use anyhow::{anyhow, bail, Context};
+use api::{ImageKind, Platform, SelfTest, VrcApiClient, World};
 use async_stream::stream;
 use axum::{
-    extract::{Path, State},
-    response::{sse::Event, Response, Sse},
-    routing::get,
-    Router,
+    body::{Body, Bytes},
+    extract::{
+        rejection::JsonRejection,
+        ws::{Message, WebSocketUpgrade},
+        Path, Query, State,
+    },
+    response::{
+        sse::{Event, KeepAlive},
+        Html, IntoResponse, Response, Sse,
+    },
+    routing::{get, post},
+    Json, Router,
 };
-use fast_qr::{convert::svg::SvgBuilder, QRBuilder, ECL};
+use card::{CardContent, CardOptions};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local,
+};
+use encoding_rs::Encoding;
+use error::ApiError;
+use fast_qr::convert::{svg::SvgBuilder, Builder};
 use figment::{
-    providers::{Format, Toml},
+    providers::{Env, Format, Serialized, Toml},
     Figment,
 };
-use futures::{pin_mut, Stream, StreamExt};
-use http::{header, StatusCode};
-use log::LogEventKind;
+use futures::{
+    future::{join_all, try_join_all},
+    pin_mut, Stream, StreamExt,
+};
+use history::{History, Visit};
+use http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use id::{InstanceAccess, InstanceId, RoomId, UserId, WorldId};
+use log::{LineCounts, LogEvent, LogEventKind, LogFileInfo, LogOptions};
+use qr::ErrorCorrection;
 use reqwest::Url;
 use serde::Deserialize;
-use serde::{de::Error, Serialize};
-use tokio::try_join;
-use tokio::{net::TcpListener, sync::watch};
-use tower_http::{services::ServeDir, trace::TraceLayer};
-use tracing::{debug, error};
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tokio::{
+    join, select,
+    time::{sleep, timeout},
+    try_join,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{broadcast, watch},
+};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    trace::TraceLayer,
+};
+use tracing::{debug, error, error_span, warn, Span};
 use tracing_subscriber::EnvFilter;
-use uuid::Uuid;
 
+mod analyze;
 mod api;
+mod cache;
+mod card;
+mod error;
+mod history;
+mod id;
 mod log;
+mod metrics;
+mod output;
+mod qr;
+mod transcode;
 
 #[derive(Deserialize)]
 #[serde(default)]
 struct Configuration {
     logs_path: Option<PathBuf>,
-    address: String,
+    address: Addresses,
+    api_address: Option<Addresses>,
     content: String,
     cache: String,
+    web_base_url: Url,
+    room_link_template: String,
+    world_info_template: Option<String>,
+    time_format: String,
+    redact_attributes: Vec<String>,
+    hide_private_instances: bool,
+    #[serde(rename = "overlay")]
+    overlays: Vec<Overlay>,
+    debug_endpoints: bool,
+    api_key: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    idle_payload: bool,
+    idle_message: Option<String>,
+    idle_image_url: Option<String>,
+    image_hosts: Vec<String>,
+    cache_size_interval: u64,
+    tail_bytes: u64,
+    replay_log: bool,
+    log_read_retries: u32,
+    watch_poll_interval_ms: u64,
+    log_poll_interval_ms: u64,
+    log_encoding: String,
+    min_display_ms: u64,
+    leave_grace_ms: u64,
+    pipeline_stale_secs: u64,
+    sse_keep_alive_secs: u64,
+    card_font: Option<PathBuf>,
+    qr_error_correction: ErrorCorrection,
+    popularity: PopularityThresholds,
+    description: DescriptionSettings,
+    image_headers: HashMap<String, String>,
+    event_log_file: Option<PathBuf>,
+    event_log_max_bytes: u64,
+    default_world_image: Option<PathBuf>,
+    default_world: Option<WorldId>,
+    friends: Vec<UserId>,
+    player_names: bool,
+    transcode_world_images: bool,
+    image_download_concurrency: usize,
+    world_cache_secs: u64,
+    hide_world_identity: bool,
+    hidden_world_text: String,
+    lifecycle_webhook_url: Option<Url>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct Overlay {
+    name: String,
+    show_image: bool,
+    show_qr: bool,
+    show_author: bool,
+    show_description: bool,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            show_image: true,
+            show_qr: true,
+            show_author: true,
+            show_description: true,
+        }
+    }
+}
+
+impl Overlay {
+    const TEMPLATE: &'static str = include_str!("overlay.html");
+
+    fn render(&self) -> String {
+        let classes = [
+            (self.show_image, "hide-image"),
+            (self.show_qr, "hide-qr"),
+            (self.show_author, "hide-author"),
+            (self.show_description, "hide-description"),
+        ]
+        .into_iter()
+        .filter(|(show, _)| !show)
+        .map(|(_, class)| class)
+        .collect::<Vec<_>>()
+        .join(" ");
+        Self::TEMPLATE.replace("{{classes}}", &classes)
+    }
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
             logs_path: None,
-            address: "127.0.0.1:37544".into(),
+            address: Addresses::One("127.0.0.1:37544".into()),
+            api_address: None,
             content: "static".into(),
             cache: "cache".into(),
+            web_base_url: Url::parse("https://vrchat.com/").unwrap(),
+            room_link_template: "{url}".into(),
+            world_info_template: None,
+            time_format: "%Y-%m-%d".into(),
+            redact_attributes: Vec::new(),
+            hide_private_instances: false,
+            overlays: Vec::new(),
+            debug_endpoints: false,
+            api_key: None,
+            username: None,
+            password: None,
+            idle_payload: false,
+            idle_message: None,
+            idle_image_url: None,
+            image_hosts: vec![
+                "api.vrchat.cloud".into(),
+                "files.vrchat.cloud".into(),
+                "assets.vrchat.com".into(),
+            ],
+            cache_size_interval: 3600,
+            tail_bytes: 0,
+            replay_log: false,
+            log_read_retries: 5,
+            watch_poll_interval_ms: 0,
+            log_poll_interval_ms: 100,
+            log_encoding: "utf-8".into(),
+            min_display_ms: 0,
+            leave_grace_ms: 0,
+            pipeline_stale_secs: 300,
+            sse_keep_alive_secs: 15,
+            card_font: None,
+            qr_error_correction: ErrorCorrection::L,
+            popularity: PopularityThresholds::default(),
+            description: DescriptionSettings::default(),
+            image_headers: HashMap::new(),
+            event_log_file: None,
+            event_log_max_bytes: 10 * 1024 * 1024,
+            default_world_image: None,
+            default_world: None,
+            friends: Vec::new(),
+            player_names: false,
+            transcode_world_images: false,
+            image_download_concurrency: 4,
+            world_cache_secs: 600,
+            hide_world_identity: false,
+            hidden_world_text: "Somewhere secret".into(),
+            lifecycle_webhook_url: None,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Addresses {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Addresses {
+    fn as_slice(&self) -> &[String] {
+        match self {
+            Addresses::One(address) => std::slice::from_ref(address),
+            Addresses::Many(addresses) => addresses,
+        }
+    }
+}
+
+const CONFIG_FILE: &str = "where-am-i.toml";
+/// Optional settings that override `CONFIG_FILE`, such as secrets kept out of a shared file.
+const LOCAL_CONFIG_FILE: &str = "where-am-i.local.toml";
+
+const FIRST_RUN_HELP: &str = r#"where-am-i could not find the VRChat log directory.
+
+Create a file named where-am-i.toml next to where-am-i and tell it where VRChat writes its logs:
+
+    logs_path = "C:\\Users\\User\\AppData\\LocalLow\\VRChat\\VRChat"
+
+On Linux and the Steam Deck, VRChat runs in Proton and the directory is usually
+
+    logs_path = "/home/user/.steam/steam/steamapps/compatdata/438100/pfx/drive_c/users/steamuser/AppData/LocalLow/VRChat/VRChat"
+
+and on macOS with CrossOver it is usually
+
+    logs_path = "/Users/user/Library/Application Support/CrossOver/Bottles/Steam/drive_c/users/crossover/AppData/LocalLow/VRChat/VRChat"
+
+Or set the VRCHAT_LOG_DIR environment variable to that directory.
+"#;
+
+const USAGE: &str = "Usage: where-am-i [--analyze <log directory>] [--logs-path <directory>] \
+[--address <address>] [--content <directory>] [--cache <directory>]";
+
+/// The settings that can be given on the command line.
+const ARG_SETTINGS: [&str; 4] = ["logs_path", "address", "content", "cache"];
+
+struct Args {
+    /// The directory to analyze instead of starting the server.
+    analyze: Option<PathBuf>,
+    /// Settings that override the configuration files and environment.
+    settings: Figment,
+}
+
+impl Args {
+    fn parse() -> anyhow::Result<Self> {
+        let mut analyze = None;
+        let mut settings = Figment::new();
+        let mut args = std::env::args_os().skip(1);
+        while let Some(arg) = args.next() {
+            let name = arg.to_str().and_then(|arg| arg.strip_prefix("--"));
+            if name == Some("analyze") {
+                let path = args.next().context("--analyze needs a log directory")?;
+                analyze = Some(PathBuf::from(path));
+                continue;
+            }
+            let Some(key) = name
+                .map(|name| name.replace('-', "_"))
+                .filter(|key| ARG_SETTINGS.contains(&key.as_str()))
+            else {
+                bail!("unknown argument {arg:?}. {USAGE}");
+            };
+            let value = args
+                .next()
+                .and_then(|value| value.into_string().ok())
+                .with_context(|| format!("{arg:?} needs a value"))?;
+            settings = settings.merge(Serialized::global(&key, value));
         }
+        Ok(Self { analyze, settings })
     }
 }
 
+/// The prefix of environment variables that override settings, like `WHEREAMI_LOGS_PATH`.
+/// `__` separates nested keys, as in `WHEREAMI_POPULARITY__HOT`.
+const ENV_PREFIX: &str = "WHEREAMI_";
+
+/// Where the configuration comes from. Later providers take precedence: command line >
+/// environment > local file > file > defaults.
+fn settings(args: Figment) -> Figment {
+    Figment::new()
+        .join(Toml::file_exact(CONFIG_FILE))
+        .merge(Toml::file_exact(LOCAL_CONFIG_FILE))
+        .merge(Env::prefixed(ENV_PREFIX).split("__"))
+        .merge(args)
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
 
-    let config: Configuration = Figment::new()
-        .join(Toml::file_exact("where-am-i.toml"))
+    let args = Args::parse()?;
+
+    let config: Configuration = settings(args.settings)
         .extract()
         .context("Invalid configuration")?;
 
+    let log_encoding = Encoding::for_label(config.log_encoding.as_bytes())
+        .with_context(|| format!("unknown log_encoding {:?}", config.log_encoding))?;
+
+    if let Some(path) = args.analyze {
+        let vrc_api = VrcApiClient::new(
+            &config.cache,
+            config.image_hosts,
+            config.image_download_concurrency,
+            Duration::from_secs(config.world_cache_secs),
+        );
+        return analyze::run(&path, log_encoding, &vrc_api).await;
+    }
+
     let found_path;
     let path = if let Some(path) = &config.logs_path {
         path
     } else {
-        found_path = log::autodetect_path()?;
+        found_path = match log::autodetect_path() {
+            Ok(path) => path,
+            Err(error) => {
+                if !std::path::Path::new(CONFIG_FILE).exists() {
+                    eprintln!("{FIRST_RUN_HELP}");
+                }
+                return Err(error.context("VRChat log directory not found"));
+            }
+        };
         &found_path
     };
 
-    let vrc_api = VrcApiClient::new(&config.cache);
+    let image_headers = image_headers(&config.image_headers)?;
+
+    let vrc_api = VrcApiClient::new(
+        &config.cache,
+        config.image_hosts,
+        config.image_download_concurrency,
+        Duration::from_secs(config.world_cache_secs),
+    );
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        if let Err(error) = vrc_api
+            .log_in(username, password, config.cache.as_ref())
+            .await
+        {
+            warn!(
+                ?error,
+                "Could not log in to VRChat, continuing without logging in"
+            );
+        }
+    }
 
-    let events = log::log_events(path);
+    if config.log_poll_interval_ms == 0 {
+        bail!("log_poll_interval_ms must be at least 1");
+    }
+    if config.sse_keep_alive_secs == 0 {
+        bail!("sse_keep_alive_secs must be at least 1");
+    }
+    if StrftimeItems::new(&config.time_format).any(|item| item == Item::Error) {
+        bail!("time_format {:?} is not a valid format", config.time_format);
+    }
+    if config.web_base_url.cannot_be_a_base() {
+        bail!("web_base_url must be a web address such as https://vrchat.com/");
+    }
+    let card_font = match &config.card_font {
+        Some(path) => {
+            let font = tokio::fs::read(path)
+                .await
+                .with_context(|| format!("card_font {path:?} read error"))?;
+            let font = FontVec::try_from_vec(font)
+                .with_context(|| format!("card_font {path:?} is not a valid font"))?;
+            Some(Arc::new(font))
+        }
+        None => None,
+    };
+    let (log_file_sender, log_file) = watch::channel(None::<LogFileInfo>);
+    let line_counts = Arc::new(LineCounts::default());
+    let events = log::log_events(
+        path,
+        LogOptions {
+            tail_bytes: config.tail_bytes,
+            replay: config.replay_log,
+            read_retries: config.log_read_retries,
+            encoding: log_encoding,
+            poll_interval: (config.watch_poll_interval_ms != 0)
+                .then(|| Duration::from_millis(config.watch_poll_interval_ms)),
+            tail_poll_interval: Duration::from_millis(config.log_poll_interval_ms),
+            current_file: log_file_sender,
+            line_counts: line_counts.clone(),
+            keep_unrecognized: config.debug_endpoints,
+        },
+    );
 
+    let (live_location_sender, mut live_location) = watch::channel(None::<Location>);
+    let live_location_sender = Arc::new(live_location_sender);
+    let (override_sender, mut location_override) = watch::channel(None::<Location>);
     let (location_sender, location) = watch::channel(None::<Location>);
+    let (activity_sender, activity) = watch::channel(Activity::default());
+    let (history_sender, history) = watch::channel(History::default());
+    let (last_exit_sender, last_exit) = watch::channel(None::<LastExit>);
+    let (shutdown_sender, shutdown) = watch::channel(false);
+    let (current_user_sender, current_user) = watch::channel(None::<CurrentUser>);
+    let (processed_sender, processed) = watch::channel(None::<ProcessedEvent>);
+    let (unrecognized_sender, unrecognized) = watch::channel(VecDeque::<String>::new());
+    let (log_events_sender, _) = broadcast::channel::<LogEvent>(LOG_EVENTS_CAPACITY);
+    let location_settings = Arc::new(LocationSettings {
+        redact_attributes: config.redact_attributes,
+        hide_private_instances: config.hide_private_instances,
+        popularity: config.popularity,
+        description: config.description,
+    });
     let location_future = {
         let vrc_api = vrc_api.clone();
+        let location_settings = location_settings.clone();
+        let log_events_sender = log_events_sender.clone();
+        let live_location_sender = live_location_sender.clone();
+        let default_world = config.default_world;
+        let friends: HashSet<UserId> = config.friends.into_iter().collect();
+        let player_names = config.player_names;
+        let mut log_file = log_file.clone();
+        let mut shutdown = shutdown.clone();
         async move {
+            // Shown until the log says otherwise, so the overlay isn't empty at startup.
+            if let Some(world_id) = default_world {
+                live_location_sender.send_replace(Some(
+                    Location::resolve(&vrc_api, world_id, None, &location_settings).await,
+                ));
+                Enrichment::spawn(&vrc_api, &live_location_sender);
+            }
             pin_mut!(events);
-            while let Some(event) = events.next().await.transpose()? {
-                debug!(?event, "Got event");
+            let mut players = Players::default();
+            loop {
+                // Stopping between events lets world lookups and their cache writes finish.
+                let event = select! {
+                    event = events.next() => event,
+                    _ = shutdown.wait_for(|&stop| stop) => break,
+                };
+                let Some(event) = event.transpose()? else {
+                    break;
+                };
+                if !matches!(event.kind, LogEventKind::Unrecognized(_)) {
+                    debug!(?event, "Got event");
+                }
+                // A new log file means VRChat was restarted.
+                if log_file.has_changed().unwrap_or(false) {
+                    log_file.borrow_and_update();
+                    history_sender.send_modify(History::start_session);
+                }
+                // Sending only fails when nobody is subscribed. Raw log lines are only read with
+                // the other debug endpoints enabled.
+                _ = log_events_sender.send(event.clone());
                 match event.kind {
                     LogEventKind::LeftRoom => {
-                        location_sender.send_replace(None);
+                        players = Players::default();
+                        history_sender.send_modify(|history| history.leave(event.timestamp));
+                        live_location_sender.send_replace(None);
+                    }
+                    LogEventKind::JoiningRoom(room_id)
+                        if !is_repeated_join(&live_location_sender, &room_id) =>
+                    {
+                        let location = Location::resolve(
+                            &vrc_api,
+                            room_id.world,
+                            Some(room_id),
+                            &location_settings,
+                        )
+                        .await;
+                        players = Players::default();
+                        history_sender
+                            .send_modify(|history| history.join(event.timestamp, &location));
+                        live_location_sender.send_replace(Some(Location {
+                            status: LocationStatus::Joining,
+                            am_owner: current_user_sender.borrow().as_ref().map(|user| {
+                                location
+                                    .full_room_id
+                                    .as_ref()
+                                    .and_then(|room| room.instance.owner())
+                                    == Some(user.id)
+                            }),
+                            ..location
+                        }));
+                        Enrichment::spawn(&vrc_api, &live_location_sender);
+                    }
+                    LogEventKind::JoiningRoom(_) => {}
+                    LogEventKind::JoiningOrCreatingRoom { name } => {
+                        if show_pending_join(&live_location_sender, name, &location_settings) {
+                            players = Players::default();
+                        }
                     }
-                    LogEventKind::JoiningRoom(room_id) => {
-                        let world = match vrc_api.get_world(room_id.world).await {
-                            Ok(world) => Some(world),
-                            Err(error) => {
-                                error!(?error, "world info error");
-                                None
+                    LogEventKind::JoinedRoom => {
+                        live_location_sender.send_if_modified(|location| match location {
+                            Some(location)
+                                if location.status == LocationStatus::Joining
+                                    || !location.world_loaded =>
+                            {
+                                location.status = LocationStatus::Joined;
+                                location.world_loaded = true;
+                                true
                             }
-                        };
-                        location_sender.send_replace(Some(Location {
-                            world_id: room_id.world,
-                            room_id,
-                            world,
+                            _ => false,
+                        });
+                    }
+                    LogEventKind::WorldLoadFailed => {
+                        live_location_sender.send_if_modified(|location| match location {
+                            Some(location) if location.world_loaded => {
+                                location.world_loaded = false;
+                                true
+                            }
+                            _ => false,
+                        });
+                    }
+                    LogEventKind::Kicked { reason } => {
+                        players = Players::default();
+                        history_sender.send_modify(|history| history.leave(event.timestamp));
+                        live_location_sender.send_replace(None);
+                        last_exit_sender.send_replace(Some(LastExit {
+                            at: history::local_time(event.timestamp),
+                            reason,
                         }));
                     }
+                    LogEventKind::Authenticated { name, user } => {
+                        current_user_sender.send_replace(Some(CurrentUser {
+                            id: user,
+                            display_name: name,
+                        }));
+                    }
+                    LogEventKind::PlayerJoined { name, user } => {
+                        players.join(name, user);
+                        players.update(&live_location_sender, &friends, player_names);
+                    }
+                    LogEventKind::PlayerLeft { name, user } => {
+                        players.leave(&name, user);
+                        players.update(&live_location_sender, &friends, player_names);
+                    }
+                    LogEventKind::ApplicationPaused(paused) => {
+                        activity_sender.send_if_modified(|activity| {
+                            let changed = activity.is_active == paused;
+                            activity.is_active = !paused;
+                            changed
+                        });
+                    }
+                    LogEventKind::Unrecognized(line) => {
+                        unrecognized_sender.send_modify(|lines| {
+                            if lines.len() == MAX_UNRECOGNIZED_LINES {
+                                lines.pop_front();
+                            }
+                            lines.push_back(line);
+                        });
+                    }
                 }
+                processed_sender.send_replace(Some(ProcessedEvent {
+                    logged_at: history::local_time(event.timestamp),
+                    processed_at: Local::now(),
+                }));
             }
             anyhow::Ok(())
         }
     };
 
-    let state = ApiState { location, vrc_api };
+    // Serves the pinned location if there is one, otherwise the location from the log.
+    let min_display = Duration::from_millis(config.min_display_ms);
+    let leave_grace = Duration::from_millis(config.leave_grace_ms);
+    let override_future = async move {
+        loop {
+            let live = live_location.borrow_and_update().clone();
+            let pinned = location_override.borrow_and_update().clone();
+            let next = pinned.or(live);
+            // Leaving is usually followed right away by joining another world, so hold it back
+            // for a moment to avoid briefly showing no location in between.
+            if next.is_none() && location_sender.borrow().is_some() && !leave_grace.is_zero() {
+                let changed = timeout(leave_grace, async {
+                    select! {
+                        changed = live_location.changed() => changed,
+                        changed = location_override.changed() => changed,
+                    }
+                })
+                .await;
+                match changed {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(_)) => return anyhow::Ok(()),
+                    Err(_) => {}
+                }
+            }
+            location_sender.send_replace(next);
+            // Changes made while waiting are seen right after, so only the latest is shown.
+            if !min_display.is_zero() {
+                sleep(min_display).await;
+            }
+            loop {
+                let changed = select! {
+                    changed = live_location.changed() => {
+                        changed.map(|()| location_override.borrow().is_none())
+                    }
+                    changed = location_override.changed() => changed.map(|()| true),
+                };
+                match changed {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(_) => return anyhow::Ok(()),
+                }
+            }
+        }
+    };
+
+    let web_base_url = Arc::new(config.web_base_url);
+    let hidden_world_text: Option<Arc<str>> = config
+        .hide_world_identity
+        .then(|| config.hidden_world_text.into());
+    let world_info_template: Option<Arc<str>> = config.world_info_template.map(Into::into);
+    let time_format: Arc<str> = config.time_format.into();
+
+    let event_log_future = {
+        let event_log_file = config.event_log_file;
+        let event_log_max_bytes = config.event_log_max_bytes;
+        let mut log_events = log_events_sender.subscribe();
+        async move {
+            let Some(path) = event_log_file else {
+                return Ok(());
+            };
+            let mut event_log = output::EventLog::open(path, event_log_max_bytes)
+                .await
+                .context("event log file error")?;
+            loop {
+                let event = match log_events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Event log fell behind");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return anyhow::Ok(()),
+                };
+                if matches!(event.kind, LogEventKind::Unrecognized(_)) {
+                    continue;
+                }
+                let line = serde_json::to_vec(&event).context("event serialization error")?;
+                if let Err(error) = event_log.append(&line).await {
+                    warn!(?error, "Event log write error");
+                }
+            }
+        }
+    };
+
+    let state = ApiState {
+        location,
+        location_override: Arc::new(override_sender),
+        live_location: live_location_sender,
+        api_key: config.api_key.map(Into::into),
+        idle: config.idle_payload.then(|| {
+            Arc::new(IdlePayload {
+                idle: true,
+                message: config.idle_message,
+                image_url: config.idle_image_url,
+            })
+        }),
+        activity,
+        vrc_api,
+        web_base_url: web_base_url.clone(),
+        image_headers: Arc::new(image_headers),
+        default_world_image: config.default_world_image.map(Into::into),
+        card_font,
+        qr_error_correction: config.qr_error_correction,
+        cards: Default::default(),
+        webp_images: config.transcode_world_images.then(Default::default),
+        room_link_template: config.room_link_template.into(),
+        world_info_template,
+        time_format,
+        hidden_world_text,
+        location_settings,
+        overlays: config.overlays.into(),
+        unrecognized,
+        history,
+        last_exit,
+        current_user,
+        processed,
+        log_file,
+        line_counts,
+        pipeline_stale: Duration::from_secs(config.pipeline_stale_secs),
+        sse_keep_alive: Duration::from_secs(config.sse_keep_alive_secs),
+        shutdown: shutdown.clone(),
+        log_events: Arc::new(log_events_sender),
+    };
 
-    let app = Router::new()
+    let mut api = Router::new()
         .route("/api/status", get(status))
+        .route("/api/status.json", get(current_status))
+        .route("/api/status.ws", get(status_socket))
+        .route("/api/activity.json", get(current_activity))
+        .route("/api/user/current.json", get(current_user_info))
+        .route("/api/events", get(log_events))
+        .route("/api/events/ws", get(log_events_socket))
+        .route("/api/ping", get(ping))
         .route("/api/world/:world/image", get(world_image))
+        .route("/api/world/:world/thumbnail", get(world_thumbnail))
         .route("/api/world/:world/qr.svg", get(world_qr_svg))
+        .route("/api/world/:world/qr.png", get(world_qr_png))
         .route("/api/world/current/info.txt", get(current_world_info))
+        .route("/api/world/current/card.png", get(current_world_card))
         .route("/api/room/:room/qr.svg", get(room_qr_svg))
+        .route("/api/room/:room/qr.png", get(room_qr_png))
+        .route("/api/room/:world/:instance/qr.svg", get(split_room_qr_svg))
         .route("/api/room/current/link.txt", get(current_room_link))
-        .fallback_service(ServeDir::new(&config.content))
-        .with_state(state)
-        .layer(TraceLayer::new_for_http());
-
-    let listener = TcpListener::bind(&*config.address)
-        .await
-        .context("network bind error")?;
+        .route("/api/room/:room/link.txt", get(room_link))
+        .route("/api/world/current/name.txt", get(current_world_name))
+        .route("/api/world/current/author.txt", get(current_world_author))
+        .route("/api/room/current/region.txt", get(current_room_region))
+        .route(
+            "/api/location/override",
+            post(set_location_override).delete(clear_location_override),
+        )
+        .route("/api/location/clear", post(clear_live_location))
+        .route("/api/history.json", get(history_json))
+        .route("/api/history.csv", get(history_csv))
+        .route("/api/worlds/recent.json", get(recent_worlds))
+        .route("/metrics", get(metrics));
+    if config.debug_endpoints {
+        api = api
+            .route("/api/debug/unrecognized", get(debug_unrecognized))
+            .route("/api/debug/last-exit", get(debug_last_exit))
+            .route("/api/debug/instance", get(debug_instance))
+            .route("/api/debug/pipeline", get(debug_pipeline))
+            .route("/api/debug/selftest", get(debug_selftest));
+    }
+    let pages = Router::new()
+        .route("/overlay/:name", get(overlay))
+        .fallback_service(ServeDir::new(&config.content));
 
-    println!("Add an OBS browser source for http://{}", config.address);
+    let mut servers = Vec::new();
+    if let Some(api_address) = &config.api_address {
+        let pages = app(pages, state.clone());
+        for listener in bind_all(&config.address, "Add an OBS browser source for").await? {
+            servers.push((listener, pages.clone()));
+        }
+        let api = app(api, state);
+        for listener in bind_all(api_address, "Serving the API on").await? {
+            servers.push((listener, api.clone()));
+        }
+    } else {
+        let app = app(api.merge(pages), state);
+        for listener in bind_all(&config.address, "Add an OBS browser source for").await? {
+            servers.push((listener, app.clone()));
+        }
+    }
 
-    try_join! {
-        location_future,
-        async {
-            axum::serve(listener, app).await.context("server error")
-        },
-    }?;
-    Ok(())
-}
+    let cache_size_future = {
+        let cache = &config.cache;
+        let cache_size_interval = config.cache_size_interval;
+        async move {
+            if cache_size_interval == 0 {
+                return Ok(());
+            }
+            cache::monitor_size(cache, Duration::from_secs(cache_size_interval)).await
+        }
+    };
 
-#[derive(Clone)]
-struct ApiState {
-    location: watch::Receiver<Option<Location>>,
-    vrc_api: VrcApiClient,
-}
+    let lifecycle_webhook = config
+        .lifecycle_webhook_url
+        .map(|url| (reqwest::Client::new(), url));
+    if let Some((client, url)) = &lifecycle_webhook {
+        tokio::spawn(send_lifecycle_event(client.clone(), url.clone(), "started"));
+    }
 
-#[derive(Clone, Copy, Debug)]
-struct WorldId(Uuid);
+    let mut tasks = pin!(async {
+        try_join! {
+            location_future,
+            until_shutdown(shutdown.clone(), override_future),
+            until_shutdown(shutdown.clone(), event_log_future),
+            until_shutdown(shutdown.clone(), cache_size_future),
+            try_join_all(servers.into_iter().map(|(listener, app)| {
+                let mut shutdown = shutdown.clone();
+                async move {
+                    axum::serve(listener, app)
+                        .with_graceful_shutdown(async move {
+                            _ = shutdown.wait_for(|&stop| stop).await;
+                        })
+                        .await
+                        .context("server error")
+                }
+            })),
+        }?;
+        anyhow::Ok(())
+    });
 
-impl fmt::Display for WorldId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "wrld_{}", self.0.as_hyphenated())
+    select! {
+        result = &mut tasks => return result,
+        result = shutdown_signal() => result?,
     }
+    debug!("Stopping");
+    shutdown_sender.send_replace(true);
+    let stopping = async {
+        if let Some((client, url)) = lifecycle_webhook {
+            send_lifecycle_event(client, url, "stopping").await;
+        }
+    };
+    stop(tasks, stopping, SHUTDOWN_TIMEOUT).await
 }
 
-impl FromStr for WorldId {
-    type Err = anyhow::Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let uuid = s
-            .strip_prefix("wrld_")
-            .ok_or_else(|| anyhow!("world ID does not begin with wrld"))?;
-        Ok(WorldId(uuid.parse()?))
+/// Waits up to `limit` for `tasks` to finish while `stopping` runs, then waits for `stopping`
+/// too, so the webhook is sent even when everything stops right away. `stopping` is limited to
+/// the same time, so a slow webhook doesn't hold up the exit.
+async fn stop(
+    tasks: impl Future<Output = anyhow::Result<()>>,
+    stopping: impl Future<Output = ()>,
+    limit: Duration,
+) -> anyhow::Result<()> {
+    let (result, _) = join!(timeout(limit, tasks), timeout(limit, stopping));
+    match result {
+        Ok(result) => result,
+        Err(_) => {
+            warn!("Timed out waiting for requests to finish, stopping anyway");
+            Ok(())
+        }
     }
 }
 
-impl Serialize for WorldId {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
+/// How long to wait for requests in progress after being asked to stop.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Waits for Ctrl+C, or for SIGTERM on Unix, which service managers like systemd send.
+async fn shutdown_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
     {
-        serializer.serialize_str(&self.to_string())
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).context("shutdown signal error")?;
+        select! {
+            result = tokio::signal::ctrl_c() => result.context("shutdown signal error"),
+            _ = terminate.recv() => Ok(()),
+        }
     }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c()
+        .await
+        .context("shutdown signal error")
 }
 
-impl<'de> Deserialize<'de> for WorldId {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s = <Cow<'de, str>>::deserialize(deserializer)?;
-        FromStr::from_str(&s).map_err(D::Error::custom)
+/// Runs a task until it finishes or shutdown is requested.
+async fn until_shutdown(
+    mut shutdown: watch::Receiver<bool>,
+    task: impl Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    select! {
+        result = task => result,
+        _ = shutdown.wait_for(|&stop| stop) => Ok(()),
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-struct UserId(Uuid);
+#[derive(Serialize)]
+struct LifecycleEvent {
+    event: &'static str,
+    version: &'static str,
+}
 
-impl fmt::Display for UserId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "usr_{}", self.0.as_hyphenated())
+/// Tells `lifecycle_webhook_url` that where-am-i started or is stopping. Failures are only logged.
+async fn send_lifecycle_event(client: reqwest::Client, url: Url, event: &'static str) {
+    let result = client
+        .post(url)
+        .json(&LifecycleEvent {
+            event,
+            version: env!("CARGO_PKG_VERSION"),
+        })
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status);
+    if let Err(error) = result {
+        warn!(?error, event, "Lifecycle webhook error");
     }
 }
 
-impl FromStr for UserId {
-    type Err = anyhow::Error;
+fn app(router: Router<ApiState>, state: ApiState) -> Router {
+    router
+        .with_state(state)
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(request_span))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let uuid = s
-            .strip_prefix("usr_")
-            .ok_or_else(|| anyhow!("user ID does not begin with usr"))?;
-        Ok(UserId(uuid.parse()?))
+/// Binds every address, skipping any that fail as long as at least one succeeds.
+async fn bind_all(addresses: &Addresses, message: &str) -> anyhow::Result<Vec<TcpListener>> {
+    let mut listeners = Vec::new();
+    let mut bind_error = None;
+    for address in addresses.as_slice() {
+        match bind(address).await {
+            Ok(listener) => {
+                println!("{message} http://{address}");
+                listeners.push(listener);
+            }
+            Err(error) => {
+                error!(?error, address, "bind error");
+                bind_error.get_or_insert(error);
+            }
+        }
+    }
+    if listeners.is_empty() {
+        return Err(bind_error.unwrap_or_else(|| anyhow!("no address in where-am-i.toml")));
     }
+    Ok(listeners)
 }
 
-impl<'de> Deserialize<'de> for UserId {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s = <Cow<'de, str>>::deserialize(deserializer)?;
-        FromStr::from_str(&s).map_err(D::Error::custom)
-    }
+fn request_span(request: &http::Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    // Created at the error level so that errors logged while handling the request always
+    // include the request ID.
+    error_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id,
+    )
 }
 
-impl Serialize for UserId {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(&self.to_string())
+async fn bind(address: &str) -> anyhow::Result<TcpListener> {
+    match TcpListener::bind(address).await {
+        Ok(listener) => Ok(listener),
+        Err(error) if error.kind() == io::ErrorKind::AddrInUse => Err(error).with_context(|| {
+            format!(
+                "{address} is already in use. Is where-am-i already running? \
+                     If not, change `address` in where-am-i.toml",
+            )
+        }),
+        Err(error) => Err(error).with_context(|| {
+            format!("network bind error for {address}. Check `address` in where-am-i.toml")
+        }),
     }
 }
 
-#[derive(Debug)]
-struct InstanceId {
-    id: u32,
-    attributes: Vec<(String, String)>,
+#[derive(Clone)]
+struct ApiState {
+    location: watch::Receiver<Option<Location>>,
+    location_override: Arc<watch::Sender<Option<Location>>>,
+    /// The location from the log, before any override.
+    live_location: Arc<watch::Sender<Option<Location>>>,
+    api_key: Option<Arc<str>>,
+    idle: Option<Arc<IdlePayload>>,
+    activity: watch::Receiver<Activity>,
+    vrc_api: VrcApiClient,
+    web_base_url: Arc<Url>,
+    image_headers: Arc<HeaderMap>,
+    default_world_image: Option<Arc<std::path::Path>>,
+    /// The font for `/api/world/current/card.png`, which is only served when one is set.
+    card_font: Option<Arc<FontVec>>,
+    qr_error_correction: ErrorCorrection,
+    cards: Arc<Mutex<CardCache>>,
+    /// WebP versions of world images when `transcode_world_images` is on, or `None` for images
+    /// that are served as they are.
+    webp_images: Option<Arc<WebpImages>>,
+    room_link_template: Arc<str>,
+    /// Replaces the default format of `/api/world/current/info.txt`.
+    world_info_template: Option<Arc<str>>,
+    /// How dates are written in `world_info_template`.
+    time_format: Arc<str>,
+    /// Replaces the world name, information and room link when `hide_world_identity` is set. The
+    /// author reads "N/A", and the card and QR codes don't show the world either.
+    hidden_world_text: Option<Arc<str>>,
+    location_settings: Arc<LocationSettings>,
+    overlays: Arc<[Overlay]>,
+    unrecognized: watch::Receiver<VecDeque<String>>,
+    history: watch::Receiver<History>,
+    last_exit: watch::Receiver<Option<LastExit>>,
+    current_user: watch::Receiver<Option<CurrentUser>>,
+    processed: watch::Receiver<Option<ProcessedEvent>>,
+    log_file: watch::Receiver<Option<LogFileInfo>>,
+    line_counts: Arc<LineCounts>,
+    pipeline_stale: Duration,
+    /// How often event streams send a comment so idle connections aren't closed by proxies.
+    sse_keep_alive: Duration,
+    /// Becomes true when where-am-i is stopping, which ends the event streams.
+    shutdown: watch::Receiver<bool>,
+    log_events: Arc<broadcast::Sender<LogEvent>>,
 }
 
-impl fmt::Display for InstanceId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.id)?;
-        for (k, v) in &self.attributes {
-            write!(f, "~{k}({v})")?;
-        }
-        Ok(())
-    }
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Location {
+    /// The room with the configured attributes redacted. Left out, rather than null, for a
+    /// pinned or default location that only names a world.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    room_id: Option<RoomId>,
+    /// The room exactly as it appeared in the log.
+    #[serde(skip)]
+    full_room_id: Option<RoomId>,
+    /// Left out while VRChat creates a room, before the log shows its ID. Only the name of the
+    /// world is known then.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    world_id: Option<WorldId>,
+    world: Option<World>,
+    platform: Platform,
+    popularity: Option<Popularity>,
+    status: LocationStatus,
+    /// Details decoded from the room ID as it appeared in the log. Redacting the attribute with
+    /// the owner's ID doesn't make a private instance look public.
+    instance: Option<InstanceInfo>,
+    /// The size of the largest download for the world, when VRChat reports it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    world_size_bytes: Option<u64>,
+    /// How many public worlds the author has published. Needs a VRChat login.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author_world_count: Option<usize>,
+    /// Whether the logged in user created the instance. Unknown until the log says who is
+    /// logged in, and for pinned locations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    am_owner: Option<bool>,
+    /// The display name of the user who created a friends or invite instance. Needs a VRChat
+    /// login, and is left out when the attribute with the owner is redacted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance_owner: Option<String>,
+    /// False after the log reports that the world failed to download or load.
+    world_loaded: bool,
+    /// How many players on the `friends` list are in the instance. Unknown without a list, and
+    /// with older VRChat builds that don't log user IDs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    friends_present: Option<usize>,
+    /// How many players are in the instance, including the local player. Unknown until the log
+    /// shows a player joining, and for pinned locations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    player_count: Option<usize>,
+    /// The display names of the players, when `player_names` is enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    players: Option<Vec<String>>,
 }
 
-impl FromStr for InstanceId {
-    type Err = anyhow::Error;
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InstanceInfo {
+    access_type: InstanceAccess,
+    region: String,
+    region_name: Option<&'static str>,
+    role_restricted: bool,
+    role_ids: Vec<String>,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut attributes = s.split('~');
-        let id = attributes.next().unwrap();
-        let attributes = attributes
-            .map(|a| {
-                let (key, rest) = a.split_once('(').context("invalid attribute")?;
-                let value = rest.strip_suffix(')').context("invalid attribute value")?;
-                Ok((key.to_owned(), value.to_owned()))
-            })
-            .collect::<anyhow::Result<Vec<_>>>();
-        Ok(Self {
-            id: id.parse().context("invalid instance id")?,
-            attributes: attributes.context("invalid attributes")?,
-        })
+impl InstanceInfo {
+    fn new(instance: &InstanceId) -> Self {
+        let role_ids: Vec<String> = instance.role_ids().into_iter().map(Into::into).collect();
+        Self {
+            access_type: instance.access(),
+            region: instance.region().into(),
+            region_name: instance.region_name(),
+            role_restricted: !role_ids.is_empty(),
+            role_ids,
+        }
     }
 }
 
-#[derive(Debug)]
-struct RoomId {
-    world: WorldId,
-    instance: InstanceId,
+/// Whether VRChat has finished loading into the location.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum LocationStatus {
+    /// VRChat is still loading the world.
+    Joining,
+    /// The world has loaded. Pinned locations are always joined.
+    Joined,
+    /// VRChat left the room. Only sent by the streaming endpoints, once, right before null.
+    Left,
 }
 
-impl fmt::Display for RoomId {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:{}", self.world, self.instance)
+/// Configuration used when building a [`Location`].
+struct LocationSettings {
+    redact_attributes: Vec<String>,
+    /// Hides join links and QR codes for instances that aren't public.
+    hide_private_instances: bool,
+    popularity: PopularityThresholds,
+    description: DescriptionSettings,
+}
+
+impl LocationSettings {
+    /// Whether join links for the room are hidden by `hide_private_instances`. This must be
+    /// checked before redacting, since removing attributes can make an instance look public.
+    fn hides_room(&self, room: &RoomId) -> bool {
+        self.hide_private_instances && room.instance.access() != InstanceAccess::Public
     }
 }
 
-impl FromStr for RoomId {
-    type Err = anyhow::Error;
+/// How busy a world generally is, based on VRChat's heat score.
+#[derive(Clone, Copy, Serialize)]
+enum Popularity {
+    Quiet,
+    Warm,
+    Hot,
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (world, instance) = s.split_once(':').context("invalid room ID")?;
-        Ok(RoomId {
-            world: world.parse().context("invalid world ID")?,
-            instance: instance.parse().context("invalid instance ID")?,
-        })
+impl Popularity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Popularity::Quiet => "Quiet",
+            Popularity::Warm => "Warm",
+            Popularity::Hot => "Hot",
+        }
     }
 }
 
-impl Serialize for RoomId {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        serializer.serialize_str(&self.to_string())
+/// The lowest heat for each [`Popularity`]. VRChat's heat is usually between 0 and 10.
+#[derive(Deserialize)]
+#[serde(default)]
+struct PopularityThresholds {
+    warm: u32,
+    hot: u32,
+}
+
+impl Default for PopularityThresholds {
+    fn default() -> Self {
+        Self { warm: 3, hot: 6 }
     }
 }
 
-impl<'de> Deserialize<'de> for RoomId {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        let s = <Cow<'de, str>>::deserialize(deserializer)?;
-        FromStr::from_str(&s).map_err(D::Error::custom)
+impl PopularityThresholds {
+    fn classify(&self, heat: u32) -> Popularity {
+        if heat >= self.hot {
+            Popularity::Hot
+        } else if heat >= self.warm {
+            Popularity::Warm
+        } else {
+            Popularity::Quiet
+        }
     }
 }
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct Location {
-    room_id: RoomId,
-    world_id: WorldId,
-    world: Option<World>,
+/// How world descriptions are cleaned up before they are sent, for single-line text sources.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct DescriptionSettings {
+    /// Joins the lines of the description with spaces.
+    single_line: bool,
+    /// Removes words that are web links.
+    strip_urls: bool,
+    /// Cuts off longer descriptions with an ellipsis. 0 keeps the whole description.
+    max_chars: usize,
 }
 
-async fn status(
-    State(ApiState { mut location, .. }): State<ApiState>,
-) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-    Sse::new(stream! {
-        {
-            let change = {
-                let location = location.borrow_and_update();
-                Event::default()
-                    .event("location")
-                    .json_data(&*location).unwrap()
-            };
+impl DescriptionSettings {
+    fn normalize(&self, description: &str) -> String {
+        let lines = description.lines().map(|line| {
+            if self.strip_urls {
+                strip_urls(line)
+            } else {
+                line.trim_end().to_owned()
+            }
+        });
+        let description = if self.single_line {
+            lines
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.trim().to_owned())
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            lines.collect::<Vec<_>>().join("\n")
+        };
+        if self.max_chars != 0 && description.chars().count() > self.max_chars {
+            let mut truncated: String = description
+                .chars()
+                .take(self.max_chars.saturating_sub(1))
+                .collect();
+            truncated.truncate(truncated.trim_end().len());
+            truncated.push('…');
+            truncated
+        } else {
+            description
+        }
+    }
 
-            yield Ok(change);
+    fn apply(&self, world: &mut World) {
+        if let Some(description) = &mut world.description {
+            *description = self.normalize(description);
         }
-        while location.changed().await.is_ok() {
-            let change = {
-                let location = location.borrow_and_update();
-                Event::default()
-                    .event("location")
-                    .json_data(&*location).unwrap()
-            };
-            yield Ok(change);
+    }
+}
+
+/// Removes the words that contain web links from a line, with the space before them. The rest of
+/// the line, including its indentation, is kept as it is.
+fn strip_urls(line: &str) -> String {
+    let is_url = |word: &str| word.contains("http://") || word.contains("https://");
+    let content = line.trim_start();
+    let indent = line.len() - content.len();
+    let mut stripped = line[..indent].to_owned();
+    let mut rest = content;
+    while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+        let end = rest[start..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |len| start + len);
+        let (space, word) = rest[..end].split_at(start);
+        if !is_url(word) {
+            // The first word that is kept follows the indentation directly.
+            if stripped.len() > indent {
+                stripped.push_str(space);
+            }
+            stripped.push_str(word);
         }
-    })
+        rest = &rest[end..];
+    }
+    stripped.truncate(stripped.trim_end().len());
+    stripped
 }
 
-async fn world_image(
-    State(ApiState { vrc_api, .. }): State<ApiState>,
-    Path(world): Path<WorldId>,
-) -> Result<Response, StatusCode> {
-    match vrc_api.get_world_image(world).await {
-        Ok(image) => Ok(image),
-        Err(error) => {
-            error!(?error, "image download error");
-            Err(StatusCode::INTERNAL_SERVER_ERROR)
+impl Location {
+    async fn resolve(
+        vrc_api: &VrcApiClient,
+        world_id: WorldId,
+        room_id: Option<RoomId>,
+        settings: &LocationSettings,
+    ) -> Self {
+        let world = match vrc_api.get_world(world_id).await {
+            Ok(world) => Some(world),
+            Err(error) => {
+                error!(?error, "world info error");
+                None
+            }
+        };
+        Self::new(Some(world_id), room_id, world, settings)
+    }
+
+    /// A room that VRChat is joining or creating, before the log shows its ID.
+    fn pending(world_name: String, settings: &LocationSettings) -> Self {
+        let world = World {
+            name: Some(world_name),
+            ..Default::default()
+        };
+        Location {
+            status: LocationStatus::Joining,
+            ..Self::new(None, None, Some(world), settings)
+        }
+    }
+
+    /// A location with the details that don't need more requests to VRChat.
+    fn new(
+        world_id: Option<WorldId>,
+        room_id: Option<RoomId>,
+        mut world: Option<World>,
+        settings: &LocationSettings,
+    ) -> Self {
+        if let Some(world) = &mut world {
+            settings.description.apply(world);
+        }
+        Location {
+            world_id,
+            instance: room_id
+                .as_ref()
+                .map(|room| InstanceInfo::new(&room.instance)),
+            room_id: room_id
+                .as_ref()
+                .map(|room| room.redact(&settings.redact_attributes)),
+            full_room_id: room_id,
+            platform: world.as_ref().map_or(Platform::Unknown, World::platform),
+            popularity: world
+                .as_ref()
+                .and_then(|world| world.heat)
+                .map(|heat| settings.popularity.classify(heat)),
+            world_size_bytes: world.as_ref().and_then(World::size_bytes),
+            author_world_count: None,
+            world,
+            status: LocationStatus::Joined,
+            am_owner: None,
+            instance_owner: None,
+            world_loaded: true,
+            friends_present: None,
+            player_count: None,
+            players: None,
         }
     }
 }
 
-async fn world_qr_svg(Path(world): Path<WorldId>) -> Response {
-    let url = format!("https://vrchat.com/home/world/{world}");
-    let qr = QRBuilder::new(url).ecl(ECL::L).build().unwrap();
-    let svg = SvgBuilder::default().to_str(&qr);
-    Response::builder()
-        .header(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8")
-        .body(svg.into())
-        .unwrap()
+/// Details of a location that need more requests to VRChat. They are looked up after the location
+/// is shown, so they don't hold it back.
+#[derive(Default)]
+struct Enrichment {
+    author_world_count: Option<usize>,
+    instance_owner: Option<String>,
 }
 
-async fn current_world_info(
-    State(ApiState { location, .. }): State<ApiState>,
-) -> Cow<'static, str> {
-    if let Some(location) = &*location.borrow() {
-        if let Some(world) = &location.world {
-            format!(
-                "\"{}\" by {}: https://vrchat.com/home/world/{}",
-                world.name.as_deref().unwrap_or("N/A"),
-                world.author_name.as_deref().unwrap_or("N/A"),
+impl Enrichment {
+    /// Starts looking up the details of the location in `sender`, and adds them once they arrive.
+    fn spawn(vrc_api: &VrcApiClient, sender: &Arc<watch::Sender<Option<Location>>>) {
+        // VRChat only answers these requests for logged in clients.
+        if !vrc_api.has_session() {
+            return;
+        }
+        let Some((world_id, room_id, author, owner)) = sender.borrow().as_ref().map(|location| {
+            (
                 location.world_id,
+                location.full_room_id.clone(),
+                location.world.as_ref().and_then(|world| world.author_id),
+                // The owner is left out when its attribute is redacted. Owner attributes that
+                // aren't valid user IDs are skipped.
+                location
+                    .room_id
+                    .as_ref()
+                    .and_then(|room| room.instance.owner()),
             )
-        } else {
-            format!("https://vrchat.com/home/world/{}", location.world_id)
+        }) else {
+            return;
+        };
+        if author.is_none() && owner.is_none() {
+            return;
         }
-        .into()
-    } else {
-        "N/A".into()
+        let vrc_api = vrc_api.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            let author_world_count = async {
+                match vrc_api.get_author_world_count(author?).await {
+                    Ok(count) => Some(count),
+                    Err(error) => {
+                        debug!(?error, "author world count error");
+                        None
+                    }
+                }
+            };
+            let instance_owner = async {
+                match vrc_api.get_user(owner?).await {
+                    Ok(user) => user.display_name,
+                    Err(error) => {
+                        debug!(?error, "instance owner error");
+                        None
+                    }
+                }
+            };
+            let (author_world_count, instance_owner) = join!(author_world_count, instance_owner);
+            Enrichment {
+                author_world_count,
+                instance_owner,
+            }
+            .apply(&sender, world_id, room_id.as_ref());
+        });
+    }
+
+    /// Adds the details to the location in `sender`, unless it has changed to another room.
+    fn apply(
+        self,
+        sender: &watch::Sender<Option<Location>>,
+        world_id: Option<WorldId>,
+        room_id: Option<&RoomId>,
+    ) -> bool {
+        sender.send_if_modified(|location| match location {
+            Some(location)
+                if location.world_id == world_id && location.full_room_id.as_ref() == room_id =>
+            {
+                location.author_world_count = self.author_world_count;
+                location.instance_owner = self.instance_owner;
+                true
+            }
+            _ => false,
+        })
     }
 }
 
-async fn room_qr_svg(Path(room): Path<RoomId>) -> Response {
-    let url = Url::parse_with_params(
-        "https://vrchat.com/home/launch",
-        &[
-            ("worldId", room.world.to_string()),
-            ("instanceId", room.instance.to_string()),
-        ],
-    )
-    .unwrap();
-    let qr = QRBuilder::new(String::from(url))
-        .ecl(ECL::L)
-        .build()
-        .unwrap();
-    let svg = SvgBuilder::default().to_str(&qr);
-    Response::builder()
-        .header(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8")
-        .body(svg.into())
-        .unwrap()
+/// Whether the room is the one already being joined. Some VRChat builds log the room ID on both
+/// the "Joining or Creating Room" line and the "Joining" line after it.
+fn is_repeated_join(live_location: &watch::Sender<Option<Location>>, room_id: &RoomId) -> bool {
+    live_location.borrow().as_ref().is_some_and(|location| {
+        location.status == LocationStatus::Joining
+            && location.full_room_id.as_ref() == Some(room_id)
+    })
 }
 
-async fn current_room_link(State(ApiState { location, .. }): State<ApiState>) -> Cow<'static, str> {
-    if let Some(location) = &*location.borrow() {
-        let url = Url::parse_with_params(
-            "https://vrchat.com/home/launch",
-            &[
-                ("worldId", location.room_id.world.to_string()),
-                ("instanceId", location.room_id.instance.to_string()),
-            ],
-        )
-        .unwrap();
-        String::from(url).into()
+/// Shows the world that VRChat is joining or creating a room in, when the log names it before
+/// the room ID. A room that is already being joined is kept, since its ID is known.
+fn show_pending_join(
+    live_location: &watch::Sender<Option<Location>>,
+    world_name: String,
+    settings: &LocationSettings,
+) -> bool {
+    live_location.send_if_modified(|location| {
+        if location
+            .as_ref()
+            .is_some_and(|location| location.status == LocationStatus::Joining)
+        {
+            return false;
+        }
+        *location = Some(Location::pending(world_name, settings));
+        true
+    })
+}
+
+/// The players in the current instance, from the player join and leave lines in the log.
+#[derive(Default)]
+struct Players {
+    /// Display names in the order the players joined.
+    names: Vec<String>,
+    ids: HashSet<UserId>,
+    /// Whether a player joined in a log line without a user ID, so the IDs are incomplete.
+    unidentified: bool,
+}
+
+impl Players {
+    fn join(&mut self, name: String, user: Option<UserId>) {
+        // VRChat sometimes logs the same join twice.
+        if !self.names.contains(&name) {
+            self.names.push(name);
+        }
+        if let Some(user) = user {
+            self.ids.insert(user);
+        } else {
+            self.unidentified = true;
+        }
+    }
+
+    fn leave(&mut self, name: &str, user: Option<UserId>) {
+        self.names.retain(|joined| joined != name);
+        if let Some(user) = user {
+            self.ids.remove(&user);
+        }
+    }
+
+    /// How many of the players are on the `friends` list, if it can be known.
+    fn friends_present(&self, friends: &HashSet<UserId>) -> Option<usize> {
+        if friends.is_empty() || self.unidentified {
+            return None;
+        }
+        Some(self.ids.intersection(friends).count())
+    }
+
+    /// Copies the players to the live location.
+    fn update(
+        &self,
+        live_location: &watch::Sender<Option<Location>>,
+        friends: &HashSet<UserId>,
+        player_names: bool,
+    ) {
+        let player_count = Some(self.names.len());
+        let players = player_names.then(|| self.names.clone());
+        let friends_present = self.friends_present(friends);
+        live_location.send_if_modified(|location| match location {
+            Some(location)
+                if location.player_count != player_count
+                    || location.players != players
+                    || location.friends_present != friends_present =>
+            {
+                location.player_count = player_count;
+                location.players = players;
+                location.friends_present = friends_present;
+                true
+            }
+            _ => false,
+        });
+    }
+}
+
+/// Whether VRChat is in the foreground. Assumed to be active until the log says otherwise.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Activity {
+    is_active: bool,
+}
+
+impl Default for Activity {
+    fn default() -> Self {
+        Self { is_active: true }
+    }
+}
+
+/// Sent instead of `null` while not in a world when `idle_payload` is enabled.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IdlePayload {
+    idle: bool,
+    message: Option<String>,
+    image_url: Option<String>,
+}
+
+/// The JSON of the current location. After leaving a room, the room is sent once more with a
+/// "left" status before null, so overlays can animate it away.
+fn location_json(
+    location: &mut watch::Receiver<Option<Location>>,
+    idle: Option<&IdlePayload>,
+    shown: &mut Option<Location>,
+) -> Vec<String> {
+    let location = location.borrow_and_update();
+    let left = match (&*location, shown.take()) {
+        (None, Some(previous)) => Some(Location {
+            status: LocationStatus::Left,
+            ..previous
+        }),
+        _ => None,
+    };
+    shown.clone_from(&location);
+    let current = match (&*location, idle) {
+        (None, Some(idle)) => serde_json::to_string(idle),
+        (location, _) => serde_json::to_string(location),
+    };
+    left.iter()
+        .map(serde_json::to_string)
+        .chain([current])
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn location_events(
+    location: &mut watch::Receiver<Option<Location>>,
+    idle: Option<&IdlePayload>,
+    shown: &mut Option<Location>,
+) -> Vec<Event> {
+    location_json(location, idle, shown)
+        .into_iter()
+        .map(|json| Event::default().event("location").data(json))
+        .collect()
+}
+
+fn activity_event(activity: &mut watch::Receiver<Activity>) -> Event {
+    let activity = activity.borrow_and_update();
+    Event::default()
+        .event("activity")
+        .json_data(*activity)
+        .unwrap()
+}
+
+fn log_file_event(log_file: &mut watch::Receiver<Option<LogFileInfo>>) -> Option<Event> {
+    let log_file = log_file.borrow_and_update();
+    Some(
+        Event::default()
+            .event("logfile")
+            .json_data(log_file.as_ref()?)
+            .unwrap(),
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+struct StatusOptions {
+    /// Whether to send the current state as soon as the client connects.
+    snapshot: bool,
+}
+
+impl Default for StatusOptions {
+    fn default() -> Self {
+        Self { snapshot: true }
+    }
+}
+
+async fn status(
+    State(ApiState {
+        mut location,
+        mut activity,
+        mut log_file,
+        idle,
+        sse_keep_alive,
+        mut shutdown,
+        ..
+    }): State<ApiState>,
+    Query(options): Query<StatusOptions>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    Sse::new(stream! {
+        let mut shown = None;
+        if options.snapshot {
+            for event in location_events(&mut location, idle.as_deref(), &mut shown) {
+                yield Ok(event);
+            }
+            yield Ok(activity_event(&mut activity));
+            if let Some(event) = log_file_event(&mut log_file) {
+                yield Ok(event);
+            }
+        } else {
+            shown.clone_from(&location.borrow_and_update());
+            activity.borrow_and_update();
+            log_file.borrow_and_update();
+        }
+        loop {
+            let change = select! {
+                changed = location.changed() => changed.map(|()| location_events(&mut location, idle.as_deref(), &mut shown)),
+                changed = activity.changed() => changed.map(|()| vec![activity_event(&mut activity)]),
+                changed = log_file.changed() => changed.map(|()| log_file_event(&mut log_file).into_iter().collect()),
+                _ = shutdown.wait_for(|&stop| stop) => break,
+            };
+            let Ok(change) = change else {
+                break;
+            };
+            for event in change {
+                yield Ok(event);
+            }
+        }
+    })
+    .keep_alive(KeepAlive::new().interval(sse_keep_alive))
+}
+
+/// When the most recent log event was written and when where-am-i finished handling it.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProcessedEvent {
+    logged_at: DateTime<Local>,
+    processed_at: DateTime<Local>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Ping {
+    now: DateTime<Local>,
+    last_event: Option<ProcessedEvent>,
+    /// The time from the log line's timestamp to the end of its processing.
+    latency_ms: Option<i64>,
+}
+
+async fn ping(State(ApiState { processed, .. }): State<ApiState>) -> Json<Ping> {
+    let last_event = *processed.borrow();
+    Json(Ping {
+        now: Local::now(),
+        last_event,
+        latency_ms: last_event
+            .map(|event| (event.processed_at - event.logged_at).num_milliseconds()),
+    })
+}
+
+/// The number of log events kept for `/api/events` clients that fall behind.
+const LOG_EVENTS_CAPACITY: usize = 64;
+
+async fn log_events(
+    State(ApiState {
+        log_events,
+        sse_keep_alive,
+        mut shutdown,
+        ..
+    }): State<ApiState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let mut receiver = log_events.subscribe();
+    Sse::new(stream! {
+        loop {
+            let received = select! {
+                received = receiver.recv() => received,
+                _ = shutdown.wait_for(|&stop| stop) => break,
+            };
+            match received {
+                Ok(event) => yield Ok(Event::default().event("log").json_data(event).unwrap()),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!(skipped, "Log event stream client fell behind");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+    .keep_alive(KeepAlive::new().interval(sse_keep_alive))
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EventsFormat {
+    #[default]
+    Json,
+    MsgPack,
+}
+
+#[derive(Deserialize)]
+struct EventsSocketOptions {
+    #[serde(default)]
+    format: EventsFormat,
+}
+
+/// Encodes an event as MessagePack, after its length as a 32-bit big-endian integer.
+fn msgpack_frame(event: &impl Serialize) -> Vec<u8> {
+    let mut frame = vec![0; 4];
+    rmp_serde::encode::write_named(&mut frame, event).unwrap();
+    let len = u32::try_from(frame.len() - 4).unwrap();
+    frame[..4].copy_from_slice(&len.to_be_bytes());
+    frame
+}
+
+/// Sends the same events as `/api/events`, one per WebSocket message.
+async fn log_events_socket(
+    State(ApiState { log_events, .. }): State<ApiState>,
+    Query(options): Query<EventsSocketOptions>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let mut receiver = log_events.subscribe();
+    upgrade.on_upgrade(move |mut socket| async move {
+        loop {
+            let received = select! {
+                received = receiver.recv() => received,
+                // Reading answers pings. Other messages from the client are ignored, except for
+                // closing the connection.
+                message = socket.recv() => match message {
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            };
+            let event = match received {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!(skipped, "Log event socket client fell behind");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            };
+            let message = match options.format {
+                EventsFormat::Json => Message::Text(serde_json::to_string(&event).unwrap()),
+                EventsFormat::MsgPack => Message::Binary(msgpack_frame(&event)),
+            };
+            if socket.send(message).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+/// Sends the data of each "location" event from `/api/status` as a JSON text message, starting
+/// with the current location.
+async fn status_socket(
+    State(ApiState {
+        mut location, idle, ..
+    }): State<ApiState>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |mut socket| async move {
+        let mut shown = None;
+        'send: loop {
+            for json in location_json(&mut location, idle.as_deref(), &mut shown) {
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break 'send;
+                }
+            }
+            // Messages from the client are ignored, except for closing the connection.
+            let closed = loop {
+                select! {
+                    changed = location.changed() => break changed.is_err(),
+                    message = socket.recv() => match message {
+                        Some(Ok(Message::Close(_)) | Err(_)) | None => break true,
+                        Some(Ok(_)) => {}
+                    },
+                }
+            };
+            if closed {
+                break;
+            }
+        }
+    })
+}
+
+/// The data of the current "location" event, for clients that poll instead of using `/api/status`.
+async fn current_status(State(ApiState { location, idle, .. }): State<ApiState>) -> Response {
+    let location = location.borrow();
+    match (&*location, idle.as_deref()) {
+        (None, Some(idle)) => Json(idle).into_response(),
+        (location, _) => Json(location).into_response(),
+    }
+}
+
+async fn current_activity(State(ApiState { activity, .. }): State<ApiState>) -> Json<Activity> {
+    Json(*activity.borrow())
+}
+
+/// The user VRChat is logged in as, from the log.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CurrentUser {
+    id: UserId,
+    display_name: String,
+}
+
+/// The logged in user, or null until the log shows who it is.
+async fn current_user_info(
+    State(ApiState { current_user, .. }): State<ApiState>,
+) -> Json<Option<CurrentUser>> {
+    Json(current_user.borrow().clone())
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LocationOverride {
+    room_id: Option<RoomId>,
+    world_id: Option<WorldId>,
+}
+
+fn check_api_key(api_key: Option<&str>, headers: &HeaderMap) -> Result<(), ApiError> {
+    // Changing state is only possible after an API key has been configured.
+    let Some(api_key) = api_key else {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "set api_key in where-am-i.toml to use this endpoint",
+        ));
+    };
+    let provided = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    // Compared in constant time so response times don't reveal how much of a guess was right.
+    if provided.is_some_and(|provided| provided.as_bytes().ct_eq(api_key.as_bytes()).into()) {
+        Ok(())
+    } else {
+        Err(ApiError::new(StatusCode::UNAUTHORIZED, "invalid API key"))
+    }
+}
+
+async fn set_location_override(
+    State(ApiState {
+        vrc_api,
+        location_override,
+        api_key,
+        location_settings,
+        ..
+    }): State<ApiState>,
+    headers: HeaderMap,
+    request: Result<Json<LocationOverride>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    check_api_key(api_key.as_deref(), &headers)?;
+    let Json(request) = request?;
+    let world_id = match (&request.room_id, request.world_id) {
+        (Some(room_id), _) => room_id.world,
+        (None, Some(world_id)) => world_id,
+        (None, None) => {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                "roomId or worldId is required",
+            ))
+        }
+    };
+    let location = Location::resolve(&vrc_api, world_id, request.room_id, &location_settings).await;
+    location_override.send_replace(Some(location));
+    Enrichment::spawn(&vrc_api, &location_override);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn clear_location_override(
+    State(ApiState {
+        location_override,
+        api_key,
+        ..
+    }): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    check_api_key(api_key.as_deref(), &headers)?;
+    location_override.send_replace(None);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Forgets the location from the log, for when VRChat exits without logging that it left.
+async fn clear_live_location(
+    State(ApiState {
+        live_location,
+        api_key,
+        ..
+    }): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    check_api_key(api_key.as_deref(), &headers)?;
+    live_location.send_replace(None);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn history_json(State(ApiState { history, .. }): State<ApiState>) -> Json<VecDeque<Visit>> {
+    Json(history.borrow().visits().clone())
+}
+
+async fn history_csv(State(ApiState { history, .. }): State<ApiState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/csv; charset=utf-8")],
+        history.borrow().to_csv(),
+    )
+}
+
+/// The number of distinct worlds listed by `/api/worlds/recent.json`.
+const RECENT_WORLDS: usize = 50;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RecentWorld {
+    world_id: WorldId,
+    last_visited_at: DateTime<Local>,
+    /// Only present when VRChat's response is still in the cache.
+    world: Option<World>,
+}
+
+async fn recent_worlds(
+    State(ApiState {
+        history,
+        vrc_api,
+        location_settings,
+        ..
+    }): State<ApiState>,
+) -> Json<Vec<RecentWorld>> {
+    let mut visits = Vec::<(WorldId, DateTime<Local>)>::new();
+    for visit in history.borrow().visits().iter().rev() {
+        if visits.len() == RECENT_WORLDS {
+            break;
+        }
+        if !visits
+            .iter()
+            .any(|(world_id, _)| *world_id == visit.world_id)
+        {
+            visits.push((visit.world_id, visit.joined_at));
+        }
+    }
+    Json(
+        join_all(visits.into_iter().map(|(world_id, last_visited_at)| {
+            let vrc_api = &vrc_api;
+            let location_settings = &location_settings;
+            async move {
+                let mut world = vrc_api.get_cached_world(world_id).await;
+                if let Some(world) = &mut world {
+                    location_settings.description.apply(world);
+                }
+                RecentWorld {
+                    world_id,
+                    last_visited_at,
+                    world,
+                }
+            }
+        }))
+        .await,
+    )
+}
+
+async fn metrics(
+    State(ApiState {
+        history,
+        line_counts,
+        vrc_api,
+        log_file,
+        processed,
+        location,
+        ..
+    }): State<ApiState>,
+) -> impl IntoResponse {
+    let mut metrics = history.borrow().to_metrics();
+    line_counts.write_metrics(&mut metrics);
+    vrc_api.write_metrics(&mut metrics);
+    metrics::write_gauge(
+        &mut metrics,
+        "where_am_i_log_file_open",
+        "1 while a log file is being read, otherwise 0.",
+        if log_file.borrow().is_some() {
+            1.0
+        } else {
+            0.0
+        },
+    );
+    if let Some(processed) = *processed.borrow() {
+        metrics::write_gauge(
+            &mut metrics,
+            "where_am_i_seconds_since_last_line",
+            "Time since where-am-i last handled a log line.",
+            (Local::now() - processed.processed_at).num_milliseconds() as f64 / 1000.0,
+        );
+    }
+    if let Some(player_count) = location.borrow().as_ref().and_then(|l| l.player_count) {
+        metrics::write_gauge(
+            &mut metrics,
+            "where_am_i_player_count",
+            "Players in the current instance, including you.",
+            player_count as f64,
+        );
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics,
+    )
+}
+
+/// The most recent time VRChat removed the user from an instance.
+#[derive(Clone, Serialize)]
+struct LastExit {
+    at: DateTime<Local>,
+    reason: Option<String>,
+}
+
+async fn debug_last_exit(
+    State(ApiState { last_exit, .. }): State<ApiState>,
+) -> Json<Option<LastExit>> {
+    Json(last_exit.borrow().clone())
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+enum PipelineStatus {
+    Ok,
+    /// A log file is being read, but nothing has been logged for a while.
+    Degraded,
+    /// Log files are no longer being read.
+    Stopped,
+}
+
+/// Whether log events are still coming in, as opposed to the server being up.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Pipeline {
+    status: PipelineStatus,
+    log_file: Option<LogFileInfo>,
+    last_event: Option<ProcessedEvent>,
+    seconds_since_last_event: Option<i64>,
+    events_processed: u64,
+    watcher_alive: bool,
+}
+
+async fn debug_pipeline(
+    State(ApiState {
+        log_file,
+        processed,
+        line_counts,
+        pipeline_stale,
+        ..
+    }): State<ApiState>,
+) -> (StatusCode, Json<Pipeline>) {
+    // The log file sender is dropped along with the log stream.
+    let watcher_alive = log_file.has_changed().is_ok();
+    let log_file = log_file.borrow().clone();
+    let last_event = *processed.borrow();
+    let seconds_since_last_event =
+        last_event.map(|event| (Local::now() - event.processed_at).num_seconds());
+    let stale = log_file.is_some()
+        && seconds_since_last_event.is_none_or(|seconds| {
+            seconds >= pipeline_stale.as_secs().try_into().unwrap_or(i64::MAX)
+        });
+    let status = if !watcher_alive {
+        PipelineStatus::Stopped
+    } else if stale {
+        PipelineStatus::Degraded
+    } else {
+        PipelineStatus::Ok
+    };
+    (
+        match status {
+            PipelineStatus::Ok => StatusCode::OK,
+            PipelineStatus::Degraded | PipelineStatus::Stopped => StatusCode::SERVICE_UNAVAILABLE,
+        },
+        Json(Pipeline {
+            status,
+            log_file,
+            last_event,
+            seconds_since_last_event,
+            events_processed: line_counts.parsed(),
+            watcher_alive,
+        }),
+    )
+}
+
+/// VRChat Home, which is public and always exists.
+const SELF_TEST_WORLD: &str = "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd";
+
+async fn debug_selftest(State(ApiState { vrc_api, .. }): State<ApiState>) -> Json<SelfTest> {
+    Json(vrc_api.self_test(SELF_TEST_WORLD.parse().unwrap()).await)
+}
+
+/// The number of unrecognized log lines kept for `/api/debug/unrecognized`.
+const MAX_UNRECOGNIZED_LINES: usize = 50;
+
+async fn debug_unrecognized(
+    State(ApiState { unrecognized, .. }): State<ApiState>,
+) -> Json<Vec<String>> {
+    Json(unrecognized.borrow().iter().cloned().collect())
+}
+
+/// The attributes of the current instance exactly as they were parsed from the log.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DebugInstance {
+    id: u32,
+    access_type: InstanceAccess,
+    attributes: Vec<(String, String)>,
+}
+
+/// Attributes whose values are left out of `/api/debug/instance`, since they let anyone join.
+const DEBUG_REDACTED_ATTRIBUTES: &[&str] = &["nonce"];
+
+async fn debug_instance(
+    State(ApiState { location, .. }): State<ApiState>,
+) -> Json<Option<DebugInstance>> {
+    let location = location.borrow();
+    Json(
+        location
+            .as_ref()
+            .and_then(|location| location.full_room_id.as_ref())
+            .map(|room| DebugInstance {
+                id: room.instance.id,
+                access_type: room.instance.access(),
+                attributes: room
+                    .instance
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = if DEBUG_REDACTED_ATTRIBUTES.contains(&key.as_str()) {
+                            "(redacted)".into()
+                        } else {
+                            value.clone()
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect(),
+            }),
+    )
+}
+
+async fn overlay(
+    State(ApiState { overlays, .. }): State<ApiState>,
+    Path(name): Path<String>,
+) -> Result<Html<String>, StatusCode> {
+    overlays
+        .iter()
+        .find(|overlay| overlay.name == name)
+        .map(|overlay| Html(overlay.render()))
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Checks the configured `image_headers` so mistakes are reported at startup.
+fn image_headers(headers: &HashMap<String, String>) -> anyhow::Result<HeaderMap> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = HeaderName::try_from(name)
+                .with_context(|| format!("invalid header name {name:?} in image_headers"))?;
+            let value = HeaderValue::try_from(value)
+                .with_context(|| format!("invalid value for {name} in image_headers"))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+async fn world_image(
+    State(state): State<ApiState>,
+    Path(world): Path<WorldId>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    proxy_world_image(state, world, ImageKind::Image, &headers).await
+}
+
+async fn world_thumbnail(
+    State(state): State<ApiState>,
+    Path(world): Path<WorldId>,
+    headers: HeaderMap,
+) -> Result<Response, StatusCode> {
+    proxy_world_image(state, world, ImageKind::Thumbnail, &headers).await
+}
+
+/// Serves one of a world's images with the configured headers. WebP transcoding and the
+/// default image only apply to the full image.
+async fn proxy_world_image(
+    ApiState {
+        vrc_api,
+        image_headers,
+        default_world_image,
+        webp_images,
+        ..
+    }: ApiState,
+    world: WorldId,
+    kind: ImageKind,
+    headers: &HeaderMap,
+) -> Result<Response, StatusCode> {
+    let mut image = vrc_api
+        .get_world_image(world, kind)
+        .await
+        .map_err(|error| {
+            error!(?error, "image download error");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if kind == ImageKind::Image {
+        if let Some(webp_images) = &webp_images {
+            image
+                .headers_mut()
+                .insert(header::VARY, HeaderValue::from_static("accept"));
+            if accepts_webp(headers) && image.status() == StatusCode::OK {
+                if let Some(webp) = webp_world_image(&vrc_api, webp_images, world).await {
+                    *image.body_mut() = webp.into();
+                    image
+                        .headers_mut()
+                        .insert(header::CONTENT_TYPE, HeaderValue::from_static("image/webp"));
+                }
+            }
+        }
+        if image.status() == StatusCode::NOT_FOUND {
+            if let Some(path) = &default_world_image {
+                match tokio::fs::read(path).await {
+                    Ok(bytes) => {
+                        let content_type = mime_guess::from_path(path).first_or_octet_stream();
+                        image = Response::builder()
+                            .header(header::CONTENT_TYPE, content_type.as_ref())
+                            .body(bytes.into())
+                            .unwrap();
+                    }
+                    Err(error) => error!(?error, ?path, "default world image read error"),
+                }
+            }
+        }
+    }
+    for (name, value) in image_headers.iter() {
+        image.headers_mut().insert(name, value.clone());
+    }
+    Ok(image)
+}
+
+/// Whether the request's Accept header lists WebP without `q=0`. Clients that only accept
+/// wildcards get the original image, since they may not be able to show WebP.
+fn accepts_webp(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|range| {
+            let mut params = range.split(';').map(str::trim);
+            params
+                .next()
+                .is_some_and(|media_type| media_type.eq_ignore_ascii_case("image/webp"))
+                && params
+                    .filter_map(|param| param.split_once('='))
+                    .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                    .is_none_or(|(_, q)| q.trim().parse::<f32>().is_ok_and(|q| q > 0.0))
+        })
+}
+
+/// The number of transcoded world images kept before the cache is emptied.
+const MAX_WEBP_IMAGES: usize = 32;
+
+/// Transcoded world images by the address of the original, which changes when the author uploads
+/// a new image.
+type WebpImages = Mutex<HashMap<Url, Option<Bytes>>>;
+
+/// Transcodes a world image to WebP, or returns `None` if the original should be served.
+async fn webp_world_image(
+    vrc_api: &VrcApiClient,
+    webp_images: &WebpImages,
+    world: WorldId,
+) -> Option<Bytes> {
+    let image_url = match vrc_api.get_world(world).await {
+        Ok(info) => info.image_url?,
+        Err(error) => {
+            error!(?error, "world info error");
+            return None;
+        }
+    };
+    if let Some(webp) = webp_images.lock().unwrap().get(&image_url) {
+        return webp.clone();
+    }
+    let original = match vrc_api.get_world_image_bytes(world).await {
+        Ok(original) => original?,
+        Err(error) => {
+            error!(?error, "image download error");
+            return None;
+        }
+    };
+    let webp = match tokio::task::spawn_blocking(move || transcode::to_webp(&original))
+        .await
+        .unwrap()
+    {
+        Ok(webp) => webp.map(Bytes::from),
+        Err(error) => {
+            warn!(?error, %world, "world image transcode error");
+            None
+        }
+    };
+    let mut webp_images = webp_images.lock().unwrap();
+    if webp_images.len() >= MAX_WEBP_IMAGES {
+        webp_images.clear();
+    }
+    webp_images.insert(image_url, webp.clone());
+    webp
+}
+
+/// The number of rendered world cards kept before the cache is emptied.
+const MAX_CARDS: usize = 16;
+
+/// What a world card shows of the world.
+#[derive(Clone, PartialEq)]
+struct CardWorld {
+    id: WorldId,
+    name: Option<String>,
+    author: Option<String>,
+    image_url: Option<Url>,
+}
+
+/// Rendered cards of the current world, so repeated requests don't redraw them.
+#[derive(Default)]
+struct CardCache {
+    world: Option<CardWorld>,
+    cards: HashMap<CardOptions, Bytes>,
+}
+
+async fn current_world_card(
+    State(ApiState {
+        location,
+        vrc_api,
+        web_base_url,
+        card_font,
+        cards,
+        hidden_world_text,
+        ..
+    }): State<ApiState>,
+    Query(options): Query<CardOptions>,
+) -> Result<Response, StatusCode> {
+    let options = options.clamped();
+    let Some(font) = card_font else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let Some(location) = location.borrow().clone() else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    // A room that is still being created has no world to show yet.
+    let Some(world_id) = location.world_id else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+    let world = location.world.as_ref();
+    let shown = CardWorld {
+        id: world_id,
+        name: match &hidden_world_text {
+            Some(hidden_world_text) => Some(hidden_world_text.to_string()),
+            None => world.and_then(|world| world.name.clone()),
+        },
+        author: world
+            .filter(|_| hidden_world_text.is_none())
+            .and_then(|world| world.author_name.clone()),
+        image_url: world.and_then(|world| world.image_url.clone()),
+    };
+    let cached = {
+        let mut cache = cards.lock().unwrap();
+        // Cards of other worlds, or of an earlier version of this one, are not shown again.
+        if cache.world.as_ref() != Some(&shown) {
+            cache.world = Some(shown.clone());
+            cache.cards.clear();
+        }
+        cache.cards.get(&options).cloned()
+    };
+    let png = match cached {
+        Some(png) => png,
+        None => {
+            let image = match vrc_api.get_world_image_bytes(world_id).await {
+                Ok(image) => image,
+                Err(error) => {
+                    error!(?error, "image download error");
+                    None
+                }
+            };
+            let content = CardContent {
+                image: image.map(Vec::from),
+                name: shown.name.clone().unwrap_or_else(|| world_id.to_string()),
+                author: shown.author.clone(),
+                // The QR code would lead to the world page.
+                qr_url: hidden_world_text
+                    .is_none()
+                    .then(|| world_url(&web_base_url, world_id).into()),
+            };
+            let png = tokio::task::spawn_blocking(move || card::render(content, &font, options))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|png| png)
+                .map_err(|error| {
+                    error!(?error, "card render error");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })?;
+            let png = Bytes::from(png);
+            let mut cache = cards.lock().unwrap();
+            // The world may have changed while the card was drawn.
+            if cache.world.as_ref() == Some(&shown) {
+                if cache.cards.len() >= MAX_CARDS {
+                    cache.cards.clear();
+                }
+                cache.cards.insert(options, png.clone());
+            }
+            png
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+fn world_url(base: &Url, world: WorldId) -> Url {
+    let mut url = base.clone();
+    // web_base_url is checked to be a base URL on startup.
+    url.path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .extend(["home", "world", &world.to_string()]);
+    url
+}
+
+/// Builds a link to the website's "Launch World" page for an instance.
+///
+/// `instanceId` keeps the attributes that identify the instance, like its owner, group, access
+/// type and region, and leaves out any others that VRChat logged along with them. Attributes in
+/// `redact_attributes` are removed before this by the callers that serve redacted rooms.
+fn launch_url(base: &Url, room: &RoomId) -> Url {
+    let mut url = base.clone();
+    // web_base_url is checked to be a base URL on startup.
+    url.path_segments_mut()
+        .unwrap()
+        .pop_if_empty()
+        .extend(["home", "launch"]);
+    url.query_pairs_mut()
+        .clear()
+        .append_pair("worldId", &room.world.to_string())
+        .append_pair("instanceId", &room.instance.launch_id().to_string());
+    url
+}
+
+/// Builds a link that opens the instance directly in a VRChat client, skipping the website.
+///
+/// The format is `vrchat://launch?ref=vrchat.com&id=<world ID>:<instance ID>`, the same link
+/// that the website's "Launch World" button opens.
+fn app_launch_url(room: &RoomId) -> Url {
+    Url::parse_with_params(
+        "vrchat://launch",
+        &[
+            ("ref", "vrchat.com"),
+            (
+                "id",
+                &format!("{}:{}", room.world, room.instance.launch_id()),
+            ),
+        ],
+    )
+    .unwrap()
+}
+
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum QrTarget {
+    #[default]
+    Web,
+    App,
+}
+
+#[derive(Deserialize)]
+struct RoomQrOptions {
+    #[serde(default)]
+    target: QrTarget,
+}
+
+/// Query parameters for the PNG QR code routes.
+#[derive(Deserialize)]
+#[serde(default)]
+struct QrPngOptions {
+    /// The width of each module, in pixels.
+    size: u32,
+}
+
+impl Default for QrPngOptions {
+    fn default() -> Self {
+        Self { size: 8 }
+    }
+}
+
+fn qr_png(
+    url: Url,
+    error_correction: ErrorCorrection,
+    options: QrPngOptions,
+) -> Result<Response, StatusCode> {
+    let qr = qr::encode(url.as_str(), error_correction).map_err(|error| {
+        error!(?error, %url, "QR code error");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let png = qr::to_png(&qr, options.size.clamp(1, 32)).map_err(|error| {
+        error!(?error, "QR code PNG error");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+async fn world_qr_png(
+    State(ApiState {
+        web_base_url,
+        qr_error_correction,
+        hidden_world_text,
+        ..
+    }): State<ApiState>,
+    Path(world): Path<WorldId>,
+    Query(options): Query<QrPngOptions>,
+) -> Result<Response, StatusCode> {
+    if hidden_world_text.is_some() {
+        return blank_qr_png(options);
+    }
+    qr_png(
+        world_url(&web_base_url, world),
+        qr_error_correction,
+        options,
+    )
+}
+
+fn blank_qr_png(options: QrPngOptions) -> Result<Response, StatusCode> {
+    let png = qr::blank_png(options.size.clamp(1, 32)).map_err(|error| {
+        error!(?error, "QR code PNG error");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(([(header::CONTENT_TYPE, "image/png")], png).into_response())
+}
+
+async fn room_qr_png(
+    State(ApiState {
+        web_base_url,
+        location_settings,
+        qr_error_correction,
+        hidden_world_text,
+        ..
+    }): State<ApiState>,
+    Path(room): Path<RoomId>,
+    Query(target): Query<RoomQrOptions>,
+    Query(options): Query<QrPngOptions>,
+) -> Result<Response, StatusCode> {
+    if hidden_world_text.is_some() || location_settings.hides_room(&room) {
+        return blank_qr_png(options);
+    }
+    let room = room.redact(&location_settings.redact_attributes);
+    qr_png(
+        match target.target {
+            QrTarget::Web => launch_url(&web_base_url, &room),
+            QrTarget::App => app_launch_url(&room),
+        },
+        qr_error_correction,
+        options,
+    )
+}
+
+/// Query parameters for the SVG QR code routes.
+#[derive(Deserialize)]
+struct QrSvgOptions {
+    /// The color of the dark modules, as hex.
+    fg: Option<String>,
+    /// The background color, as hex.
+    bg: Option<String>,
+    /// The width of the quiet zone around the code, in modules.
+    margin: Option<usize>,
+}
+
+/// The largest quiet zone allowed by `?margin=`.
+const MAX_QR_MARGIN: usize = 16;
+
+fn qr_svg(
+    url: Url,
+    error_correction: ErrorCorrection,
+    options: QrSvgOptions,
+) -> Result<Response, StatusCode> {
+    let color = |color: Option<String>| {
+        color
+            .map(|color| qr::parse_hex_color(&color).ok_or(StatusCode::BAD_REQUEST))
+            .transpose()
+    };
+    let fg = color(options.fg)?;
+    let bg = color(options.bg)?;
+    if options.margin.is_some_and(|margin| margin > MAX_QR_MARGIN) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let qr = qr::encode(url.as_str(), error_correction).map_err(|error| {
+        error!(?error, %url, "QR code error");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let mut builder = SvgBuilder::default();
+    if let Some(fg) = fg {
+        builder.module_color(fg);
+    }
+    if let Some(bg) = bg {
+        builder.background_color(bg);
+    }
+    if let Some(margin) = options.margin {
+        builder.margin(margin);
+    }
+    let svg = builder.to_str(&qr);
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8")
+        .body(svg.into())
+        .unwrap())
+}
+
+async fn world_qr_svg(
+    State(ApiState {
+        web_base_url,
+        qr_error_correction,
+        hidden_world_text,
+        ..
+    }): State<ApiState>,
+    Path(world): Path<WorldId>,
+    Query(options): Query<QrSvgOptions>,
+) -> Result<Response, StatusCode> {
+    if hidden_world_text.is_some() {
+        return Ok(blank_qr_svg());
+    }
+    qr_svg(
+        world_url(&web_base_url, world),
+        qr_error_correction,
+        options,
+    )
+}
+
+/// The text served by `/api/world/current/info.txt`.
+fn world_info_text(
+    location: Option<&Location>,
+    web_base_url: &Url,
+    hidden_world_text: Option<&str>,
+    template: Option<&str>,
+    time_format: &str,
+) -> String {
+    if let (Some(_), Some(hidden_world_text)) = (location, hidden_world_text) {
+        hidden_world_text.into()
+    } else if let Some(location) = location {
+        let url = location
+            .world_id
+            .map(|world_id| world_url(web_base_url, world_id));
+        let url = url.as_ref().map_or("N/A", Url::as_str);
+        let world = location.world.as_ref();
+        if let Some(template) = template {
+            fill_template(template, |placeholder| {
+                Some(match placeholder {
+                    "name" => world
+                        .and_then(|w| w.name.as_deref())
+                        .unwrap_or("N/A")
+                        .into(),
+                    "author" => world
+                        .and_then(|w| w.author_name.as_deref())
+                        .unwrap_or("N/A")
+                        .into(),
+                    "url" => url.into(),
+                    "platform" => location.platform.description().unwrap_or("N/A").into(),
+                    "accessType" => location
+                        .instance
+                        .as_ref()
+                        .map_or("N/A", |instance| instance.access_type.as_str())
+                        .into(),
+                    "region" => location
+                        .instance
+                        .as_ref()
+                        .map_or("N/A", |instance| instance.region.as_str())
+                        .into(),
+                    "popularity" => location.popularity.map_or("N/A", Popularity::as_str).into(),
+                    "updated" => world.and_then(|w| w.updated_at).map_or("N/A".into(), |t| {
+                        t.with_timezone(&Local)
+                            .format(time_format)
+                            .to_string()
+                            .into()
+                    }),
+                    _ => return None,
+                })
+            })
+        } else if let Some(world) = world {
+            let platform = location
+                .platform
+                .description()
+                .map(|platform| format!(" ({platform})"))
+                .unwrap_or_default();
+            format!(
+                "\"{}\" by {}{platform}: {url}",
+                world.name.as_deref().unwrap_or("N/A"),
+                world.author_name.as_deref().unwrap_or("N/A"),
+            )
+        } else {
+            url.into()
+        }
     } else {
         "N/A".into()
     }
 }
+
+/// Replaces each `{placeholder}` in a template with its value. Placeholders without a value are
+/// left as they are, so a typo shows up in the text instead of emptying it.
+fn fill_template<'a>(template: &str, value: impl Fn(&str) -> Option<Cow<'a, str>>) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        match value(&rest[1..end]) {
+            Some(value) => text.push_str(&value),
+            None => text.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    text.push_str(rest);
+    text
+}
+
+async fn current_world_info(
+    State(ApiState {
+        location,
+        web_base_url,
+        hidden_world_text,
+        world_info_template,
+        time_format,
+        ..
+    }): State<ApiState>,
+) -> String {
+    world_info_text(
+        location.borrow().as_ref(),
+        &web_base_url,
+        hidden_world_text.as_deref(),
+        world_info_template.as_deref(),
+        &time_format,
+    )
+}
+
+fn blank_qr_svg() -> Response {
+    (
+        [(header::CONTENT_TYPE, "image/svg+xml; charset=utf-8")],
+        qr::blank_svg(),
+    )
+        .into_response()
+}
+
+async fn room_qr_svg(
+    State(ApiState {
+        web_base_url,
+        location_settings,
+        qr_error_correction,
+        hidden_world_text,
+        ..
+    }): State<ApiState>,
+    Path(room): Path<RoomId>,
+    Query(target): Query<RoomQrOptions>,
+    Query(options): Query<QrSvgOptions>,
+) -> Result<Response, StatusCode> {
+    if hidden_world_text.is_some() || location_settings.hides_room(&room) {
+        return Ok(blank_qr_svg());
+    }
+    let room = room.redact(&location_settings.redact_attributes);
+    qr_svg(
+        match target.target {
+            QrTarget::Web => launch_url(&web_base_url, &room),
+            QrTarget::App => app_launch_url(&room),
+        },
+        qr_error_correction,
+        options,
+    )
+}
+
+/// Like [`room_qr_svg`], with the world and instance as separate path segments.
+async fn split_room_qr_svg(
+    state: State<ApiState>,
+    Path((world, instance)): Path<(WorldId, InstanceId)>,
+    target: Query<RoomQrOptions>,
+    options: Query<QrSvgOptions>,
+) -> Result<Response, StatusCode> {
+    room_qr_svg(state, Path(RoomId { world, instance }), target, options).await
+}
+
+/// Fills in the `{url}`, `{world_name}` and `{world_size}` placeholders of `room_link_template`.
+fn render_room_link(template: &str, url: Url, world: Option<&World>) -> String {
+    fill_template(template, |placeholder| {
+        Some(match placeholder {
+            "url" => url.as_str().into(),
+            "world_name" => world
+                .and_then(|w| w.name.as_deref())
+                .unwrap_or("N/A")
+                .into(),
+            "world_size" => world
+                .and_then(World::size_bytes)
+                .map_or_else(|| "N/A".into(), format_size)
+                .into(),
+            _ => return None,
+        })
+    })
+}
+
+/// Formats a number of bytes like "12.3 MB" or "1.2 GB".
+fn format_size(bytes: u64) -> String {
+    const MB: f64 = 1024.0 * 1024.0;
+    const GB: f64 = MB * 1024.0;
+    let bytes = bytes as f64;
+    if bytes >= GB {
+        format!("{:.1} GB", bytes / GB)
+    } else {
+        format!("{:.1} MB", bytes / MB)
+    }
+}
+
+async fn room_link(
+    State(ApiState {
+        location,
+        vrc_api,
+        web_base_url,
+        room_link_template,
+        location_settings,
+        ..
+    }): State<ApiState>,
+    Path(room): Path<RoomId>,
+) -> String {
+    if location_settings.hides_room(&room) {
+        return "N/A".into();
+    }
+    let room = room.redact(&location_settings.redact_attributes);
+    let current_world = location
+        .borrow()
+        .as_ref()
+        .filter(|l| l.world_id == Some(room.world))
+        .and_then(|l| l.world.clone());
+    let world = match current_world {
+        Some(world) => Some(world),
+        None => match vrc_api.get_world(room.world).await {
+            Ok(world) => Some(world),
+            Err(error) => {
+                error!(?error, "world info error");
+                None
+            }
+        },
+    };
+    render_room_link(
+        &room_link_template,
+        launch_url(&web_base_url, &room),
+        world.as_ref(),
+    )
+}
+
+async fn current_room_link(
+    State(ApiState {
+        location,
+        web_base_url,
+        room_link_template,
+        hidden_world_text,
+        location_settings,
+        api_key,
+        ..
+    }): State<ApiState>,
+    headers: HeaderMap,
+) -> Result<Cow<'static, str>, ApiError> {
+    check_room_link_access(api_key.as_deref(), &headers)?;
+    if hidden_world_text.is_some() {
+        return Ok("N/A".into());
+    }
+    if let Some(location) = &*location.borrow() {
+        if let Some(room_id) = &location.full_room_id {
+            if location_settings.hides_room(room_id) {
+                return Ok("N/A".into());
+            }
+            return Ok(render_room_link(
+                &room_link_template,
+                launch_url(&web_base_url, room_id),
+                location.world.as_ref(),
+            )
+            .into());
+        }
+    }
+    Ok("N/A".into())
+}
+
+/// The link to the current room has every attribute, so once an API key is configured only
+/// requests that send it can get the link.
+fn check_room_link_access(api_key: Option<&str>, headers: &HeaderMap) -> Result<(), ApiError> {
+    match api_key {
+        Some(_) => check_api_key(api_key, headers),
+        None => Ok(()),
+    }
+}
+
+fn no_cache_text(text: &str) -> impl IntoResponse {
+    ([(header::CACHE_CONTROL, "no-cache")], text.to_owned())
+}
+
+/// The text served by `/api/world/current/name.txt`.
+fn world_name_text<'a>(
+    location: Option<&'a Location>,
+    hidden_world_text: Option<&'a str>,
+) -> &'a str {
+    match (location, hidden_world_text) {
+        (Some(_), Some(hidden_world_text)) => hidden_world_text,
+        _ => location
+            .and_then(|l| l.world.as_ref()?.name.as_deref())
+            .unwrap_or("N/A"),
+    }
+}
+
+async fn current_world_name(
+    State(ApiState {
+        location,
+        hidden_world_text,
+        ..
+    }): State<ApiState>,
+) -> impl IntoResponse {
+    let location = location.borrow();
+    no_cache_text(world_name_text(
+        location.as_ref(),
+        hidden_world_text.as_deref(),
+    ))
+}
+
+async fn current_world_author(
+    State(ApiState {
+        location,
+        hidden_world_text,
+        ..
+    }): State<ApiState>,
+) -> impl IntoResponse {
+    let location = location.borrow();
+    no_cache_text(
+        location
+            .as_ref()
+            .filter(|_| hidden_world_text.is_none())
+            .and_then(|l| l.world.as_ref()?.author_name.as_deref())
+            .unwrap_or("N/A"),
+    )
+}
+
+async fn current_room_region(
+    State(ApiState { location, .. }): State<ApiState>,
+) -> impl IntoResponse {
+    let location = location.borrow();
+    no_cache_text(
+        match location.as_ref().and_then(|l| l.full_room_id.as_ref()) {
+            Some(room_id) => room_id.instance.region(),
+            None => "N/A",
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bearer(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Bearer {key}").parse().unwrap(),
+        );
+        headers
+    }
+
+    const ROOM: &str = "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~private(usr_c1644b5b-3ca4-45b4-97c6-a2a0de70d469)~region(eu)~nonce(c3f8b9e2-1d4a-4f6b-9a7e-2b5c8d0e1f3a)";
+
+    fn location_settings() -> LocationSettings {
+        LocationSettings {
+            redact_attributes: Vec::new(),
+            hide_private_instances: false,
+            popularity: PopularityThresholds::default(),
+            description: DescriptionSettings::default(),
+        }
+    }
+
+    fn location(world: &str) -> Location {
+        let room: RoomId = ROOM.parse().unwrap();
+        Location::new(
+            Some(room.world),
+            Some(room),
+            Some(serde_json::from_str(world).unwrap()),
+            &location_settings(),
+        )
+    }
+
+    fn world_info(location: &Location, template: &str) -> String {
+        world_info_text(
+            Some(location),
+            &Url::parse("https://vrchat.com/").unwrap(),
+            None,
+            Some(template),
+            "%Y-%m-%d",
+        )
+    }
+
+    #[test]
+    // Jail's closures return figment::Error, which is large.
+    #[allow(clippy::result_large_err)]
+    fn environment_settings() {
+        figment::Jail::expect_with(|jail| {
+            jail.set_env("WHEREAMI_POPULARITY__HOT", "9");
+            jail.set_env(
+                "WHEREAMI_IMAGE_HOSTS",
+                r#"["api.vrchat.cloud", "example.com"]"#,
+            );
+            let config: Configuration = settings(Figment::new()).extract()?;
+            assert_eq!(config.popularity.hot, 9);
+            assert_eq!(config.image_hosts, ["api.vrchat.cloud", "example.com"]);
+
+            // The command line replaces the environment.
+            let args = Figment::from(Serialized::global("popularity.hot", 4));
+            let config: Configuration = settings(args).extract()?;
+            assert_eq!(config.popularity.hot, 4);
+            Ok(())
+        });
+    }
+
+    #[tokio::test]
+    async fn stopping_webhook_outlives_tasks() {
+        let sent = Arc::new(Mutex::new(false));
+        let stopping = {
+            let sent = sent.clone();
+            async move {
+                sleep(Duration::from_millis(50)).await;
+                *sent.lock().unwrap() = true;
+            }
+        };
+        stop(async { Ok(()) }, stopping, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(*sent.lock().unwrap());
+    }
+
+    #[tokio::test]
+    async fn stop_times_out() {
+        let result = stop(
+            futures::future::pending(),
+            futures::future::pending(),
+            Duration::from_millis(50),
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn redacted_owner_keeps_access_type() {
+        let room: RoomId = ROOM.parse().unwrap();
+        let settings = LocationSettings {
+            redact_attributes: vec!["private".into()],
+            ..location_settings()
+        };
+        let location = Location::new(Some(room.world), Some(room), None, &settings);
+        let json = serde_json::to_value(&location).unwrap();
+        assert_eq!(json["instance"]["accessType"], "invite");
+        assert!(!json["roomId"].as_str().unwrap().contains("usr_"));
+    }
+
+    #[test]
+    fn hidden_world_name() {
+        let location = location(r#"{"name": "The Black Cat"}"#);
+        assert_eq!(world_name_text(Some(&location), None), "The Black Cat");
+        assert_eq!(
+            world_name_text(Some(&location), Some("Somewhere secret")),
+            "Somewhere secret"
+        );
+        assert_eq!(world_name_text(None, Some("Somewhere secret")), "N/A");
+    }
+
+    #[test]
+    fn world_info_updated() {
+        let location = location(
+            r#"{
+                "name": "The Black Cat",
+                "authorName": "Fiona",
+                "created_at": "2017-12-01T12:00:00.000Z",
+                "updated_at": "2024-05-01T12:00:00.000Z"
+            }"#,
+        );
+        let world = location.world.as_ref().unwrap();
+        assert!(world.created_at.is_some());
+        assert_eq!(
+            world_info(&location, "{name} was updated {updated}"),
+            "The Black Cat was updated 2024-05-01"
+        );
+    }
+
+    #[test]
+    fn world_info_popularity() {
+        let hot = location(r#"{"name": "The Black Cat", "heat": 7}"#);
+        assert_eq!(world_info(&hot, "{popularity}"), "Hot");
+        let unknown = location(r#"{"name": "The Black Cat"}"#);
+        assert_eq!(world_info(&unknown, "{popularity}"), "N/A");
+    }
+
+    #[test]
+    fn world_info_without_updated() {
+        let location = location(r#"{"name": "The Black Cat"}"#);
+        assert_eq!(world_info(&location, "updated {updated}"), "updated N/A");
+    }
+
+    const PACKAGED_WORLD: &str = r#"{
+        "name": "The Black Cat",
+        "unityPackages": [
+            {"platform": "standalonewindows", "sizeInBytes": 47400000},
+            {"platform": "android", "sizeInBytes": 9800000}
+        ]
+    }"#;
+
+    #[test]
+    fn world_size_bytes() {
+        let json = serde_json::to_value(location(PACKAGED_WORLD)).unwrap();
+        assert_eq!(json["worldSizeBytes"], 47400000);
+        let json = serde_json::to_value(location(r#"{"name": "The Black Cat"}"#)).unwrap();
+        assert!(json.get("worldSizeBytes").is_none());
+    }
+
+    #[test]
+    fn room_link_world_size() {
+        let url = Url::parse("https://vrchat.com/home/launch").unwrap();
+        let world: World = serde_json::from_str(PACKAGED_WORLD).unwrap();
+        assert_eq!(
+            render_room_link("{world_name} ({world_size})", url.clone(), Some(&world)),
+            "The Black Cat (45.2 MB)"
+        );
+        assert_eq!(render_room_link("{world_size}", url, None), "N/A");
+    }
+
+    #[test]
+    fn format_sizes() {
+        assert_eq!(format_size(512 * 1024), "0.5 MB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024 / 2), "1.5 GB");
+    }
+
+    #[test]
+    fn enrichment_applies_to_same_room() {
+        let joined = location(r#"{"name": "The Black Cat"}"#);
+        let (world_id, room_id) = (joined.world_id, joined.full_room_id.clone());
+        let (sender, receiver) = watch::channel(Some(joined));
+        let enrichment = Enrichment {
+            author_world_count: Some(12),
+            instance_owner: Some("Fiona".into()),
+        };
+        assert!(enrichment.apply(&sender, world_id, room_id.as_ref()));
+        let json = serde_json::to_value(&*receiver.borrow()).unwrap();
+        assert_eq!(json["authorWorldCount"], 12);
+        assert_eq!(json["instanceOwner"], "Fiona");
+    }
+
+    #[test]
+    fn enrichment_skips_other_room() {
+        let joined = location(r#"{"name": "The Black Cat"}"#);
+        let world_id = joined.world_id;
+        let (sender, receiver) = watch::channel(Some(joined));
+        let enrichment = Enrichment {
+            author_world_count: Some(12),
+            instance_owner: Some("Fiona".into()),
+        };
+        let other: RoomId = "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:67890"
+            .parse()
+            .unwrap();
+        assert!(!enrichment.apply(&sender, world_id, Some(&other)));
+        let json = serde_json::to_value(&*receiver.borrow()).unwrap();
+        assert!(json.get("authorWorldCount").is_none());
+        assert!(json.get("instanceOwner").is_none());
+    }
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn webp_accepted() {
+        assert!(accepts_webp(&accept(
+            "image/avif,image/webp,image/apng,image/*,*/*;q=0.8"
+        )));
+        assert!(accepts_webp(&accept("image/png, image/WebP; q=0.5")));
+    }
+
+    #[test]
+    fn webp_not_accepted() {
+        assert!(!accepts_webp(&HeaderMap::new()));
+        assert!(!accepts_webp(&accept("image/*,*/*;q=0.8")));
+        assert!(!accepts_webp(&accept("image/webp;q=0, image/png")));
+        assert!(!accepts_webp(&accept("image/webp;q=0.000")));
+        assert!(!accepts_webp(&accept("image/webpx")));
+    }
+
+    const LINKED_DESCRIPTION: &str = "Welcome to the café!\n\n  Discord: https://discord.gg/cafe\nPhotos by  Fiona https://example.com/photos and friends\nhttps://example.com/a https://example.com/b\n";
+
+    #[test]
+    fn strip_urls_keeps_lines() {
+        let settings = DescriptionSettings {
+            strip_urls: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.normalize(LINKED_DESCRIPTION),
+            "Welcome to the café!\n\n  Discord:\nPhotos by  Fiona and friends\n"
+        );
+    }
+
+    #[test]
+    fn strip_urls_single_line() {
+        let settings = DescriptionSettings {
+            single_line: true,
+            strip_urls: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            settings.normalize(LINKED_DESCRIPTION),
+            "Welcome to the café! Discord: Photos by  Fiona and friends"
+        );
+    }
+
+    #[test]
+    fn strip_leading_url() {
+        assert_eq!(strip_urls("  https://example.com see above"), "  see above");
+    }
+
+    #[test]
+    fn pending_join_shows_world_name() {
+        let (sender, receiver) = watch::channel(None);
+        assert!(show_pending_join(
+            &sender,
+            "The Black Cat".into(),
+            &location_settings()
+        ));
+        let json = serde_json::to_value(&*receiver.borrow()).unwrap();
+        assert_eq!(json["world"]["name"], "The Black Cat");
+        assert_eq!(json["status"], "joining");
+        assert!(json.get("worldId").is_none());
+        assert!(json.get("roomId").is_none());
+    }
+
+    #[test]
+    fn pending_join_keeps_known_room() {
+        let joining = Location {
+            status: LocationStatus::Joining,
+            ..location(r#"{"name": "The Black Cat"}"#)
+        };
+        let (sender, receiver) = watch::channel(Some(joining));
+        assert!(!show_pending_join(
+            &sender,
+            "The Black Cat".into(),
+            &location_settings()
+        ));
+        assert!(receiver.borrow().as_ref().unwrap().full_room_id.is_some());
+    }
+
+    #[test]
+    fn pending_location_info() {
+        let pending = Location::pending("The Black Cat".into(), &location_settings());
+        assert_eq!(world_info(&pending, "{name}: {url}"), "The Black Cat: N/A");
+    }
+
+    #[test]
+    fn left_status_before_null() {
+        let (sender, mut receiver) = watch::channel(Some(location(r#"{"name": "The Black Cat"}"#)));
+        let mut shown = None;
+        let joined = location_json(&mut receiver, None, &mut shown);
+        assert_eq!(joined.len(), 1);
+        assert!(joined[0].contains(r#""status":"joined""#));
+
+        sender.send_replace(None);
+        let left = location_json(&mut receiver, None, &mut shown);
+        assert_eq!(left.len(), 2);
+        assert!(left[0].contains(r#""status":"left""#));
+        assert!(left[0].contains("The Black Cat"));
+        assert_eq!(left[1], "null");
+
+        // The room is only reported as left once.
+        assert_eq!(location_json(&mut receiver, None, &mut shown), ["null"]);
+    }
+
+    #[test]
+    fn msgpack_frame_is_length_prefixed() {
+        let event = LogEvent {
+            timestamp: "2024-05-01T20:13:05".parse().unwrap(),
+            kind: LogEventKind::JoiningRoom(ROOM.parse().unwrap()),
+        };
+        let frame = msgpack_frame(&event);
+        let (len, payload) = frame.split_at(4);
+        assert_eq!(
+            u32::from_be_bytes(len.try_into().unwrap()) as usize,
+            payload.len()
+        );
+        let decoded: serde_json::Value = rmp_serde::from_slice(payload).unwrap();
+        assert_eq!(decoded["kind"]["type"], "joiningRoom");
+        assert_eq!(decoded["kind"]["data"], ROOM);
+    }
+
+    #[test]
+    fn app_launch_url_round_trip() {
+        let room: RoomId = ROOM.parse().unwrap();
+        let url = app_launch_url(&room);
+        assert_eq!(url.scheme(), "vrchat");
+        assert_eq!(url.host_str(), Some("launch"));
+        let id = url
+            .query_pairs()
+            .find(|(key, _)| key == "id")
+            .map(|(_, id)| id.into_owned())
+            .unwrap();
+        assert_eq!(id.parse::<RoomId>().unwrap(), room);
+    }
+
+    #[test]
+    fn group_launch_url() {
+        let room: RoomId = "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd:12345~group(grp_71a7ff59-112c-4e78-a990-c7cc650776e5)~groupAccessType(public)~region(eu)~debugInfo(x)"
+            .parse()
+            .unwrap();
+        let url = launch_url(&Url::parse("https://vrchat.com/").unwrap(), &room);
+        assert_eq!(url.path(), "/home/launch");
+        let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(
+            query["worldId"],
+            "wrld_4432ea9b-729c-46e3-8eaf-846aa0a37fdd"
+        );
+        assert_eq!(
+            query["instanceId"],
+            "12345~group(grp_71a7ff59-112c-4e78-a990-c7cc650776e5)~groupAccessType(public)~region(eu)"
+        );
+    }
+
+    #[test]
+    fn room_link_open_without_api_key() {
+        assert!(check_room_link_access(None, &HeaderMap::new()).is_ok());
+    }
+
+    #[test]
+    fn room_link_accepts_api_key() {
+        assert!(check_room_link_access(Some("secret"), &bearer("secret")).is_ok());
+    }
+
+    #[test]
+    fn room_link_rejects_missing_or_wrong_api_key() {
+        assert!(check_room_link_access(Some("secret"), &HeaderMap::new()).is_err());
+        assert!(check_room_link_access(Some("secret"), &bearer("wrong")).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fmt::Write;
+
+/// Upper bounds of the world visit duration buckets, in seconds.
+pub const WORLD_DURATION_BUCKETS: [f64; 8] =
+    [60.0, 300.0, 900.0, 1800.0, 3600.0, 7200.0, 14400.0, 28800.0];
+
+/// A cumulative histogram in the Prometheus text format.
+pub struct Histogram<const N: usize> {
+    bounds: [f64; N],
+    buckets: [u64; N],
+    count: u64,
+    sum: f64,
+}
+
+impl<const N: usize> Histogram<N> {
+    pub fn new(bounds: [f64; N]) -> Self {
+        Self {
+            bounds,
+            buckets: [0; N],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        for (bound, bucket) in self.bounds.iter().zip(&mut self.buckets) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+
+    pub fn write(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} histogram").unwrap();
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {bucket}").unwrap();
+        }
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", self.count).unwrap();
+        writeln!(out, "{name}_sum {}", self.sum).unwrap();
+        writeln!(out, "{name}_count {}", self.count).unwrap();
+    }
+}
+
+/// Writes a counter in the Prometheus text format.
+pub fn write_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} counter").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+/// Writes a gauge in the Prometheus text format.
+pub fn write_gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} gauge").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
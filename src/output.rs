@@ -0,0 +1,190 @@
+use std::{
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+    time::sleep,
+};
+use tracing::debug;
+
+/// How many times to retry replacing a file while another program has it open.
+const MAX_RETRIES: u32 = 5;
+
+/// Replaces the contents of a file so that readers never see it partially written.
+///
+/// The contents are written to a temporary file next to `path`, which then replaces it. On
+/// Windows this uses `ReplaceFileW`, which fails while another program, such as OBS, has the file
+/// open without allowing deletion, so replacing is retried with a short backoff.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_name = path.file_name().map(OsString::from).unwrap_or_default();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+    tokio::fs::write(&temp_path, contents).await?;
+
+    let mut delay = Duration::from_millis(10);
+    let mut retries = 0;
+    loop {
+        match replace(&temp_path, path).await {
+            Ok(()) => return Ok(()),
+            Err(error) if retries < MAX_RETRIES && is_busy(&error) => {
+                debug!(?error, ?path, retries, "File busy, retrying");
+                sleep(delay).await;
+                delay *= 2;
+                retries += 1;
+            }
+            Err(error) => {
+                _ = tokio::fs::remove_file(&temp_path).await;
+                return Err(error);
+            }
+        }
+    }
+}
+
+#[cfg(windows)]
+async fn replace(temp_path: &Path, path: &Path) -> io::Result<()> {
+    use windows::{
+        core::{HSTRING, PCWSTR},
+        Win32::Storage::FileSystem::{ReplaceFileW, REPLACEFILE_IGNORE_MERGE_ERRORS},
+    };
+
+    let replaced = HSTRING::from(path);
+    let replacement = HSTRING::from(temp_path);
+    let result = tokio::task::spawn_blocking(move || unsafe {
+        ReplaceFileW(
+            &replaced,
+            &replacement,
+            PCWSTR::null(),
+            REPLACEFILE_IGNORE_MERGE_ERRORS,
+            None,
+            None,
+        )
+    })
+    .await?
+    // The errors are Win32 error codes wrapped in an HRESULT.
+    .map_err(|error| io::Error::from_raw_os_error(error.code().0 & 0xFFFF));
+    match result {
+        // There has to be a file to replace, so the first write is a plain rename.
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            tokio::fs::rename(temp_path, path).await
+        }
+        result => result,
+    }
+}
+
+#[cfg(not(windows))]
+async fn replace(temp_path: &Path, path: &Path) -> io::Result<()> {
+    tokio::fs::rename(temp_path, path).await
+}
+
+/// Whether replacing a file failed because another program has it open.
+fn is_busy(error: &io::Error) -> bool {
+    // ERROR_SHARING_VIOLATION, ERROR_UNABLE_TO_REMOVE_REPLACED and
+    // ERROR_UNABLE_TO_MOVE_REPLACEMENT.
+    error.kind() == io::ErrorKind::PermissionDenied
+        || cfg!(windows) && matches!(error.raw_os_error(), Some(32 | 1175 | 1176))
+}
+
+/// How many rotated event log files are kept besides the current one.
+const EVENT_LOG_BACKUPS: u32 = 5;
+
+/// An append-only file of JSON lines that is rotated when it grows too large.
+///
+/// When an append would take the file past `max_bytes`, it is renamed to `<name>.1`, the older
+/// `<name>.1` to `<name>.2` and so on, and a new file is started.
+pub struct EventLog {
+    path: PathBuf,
+    max_bytes: u64,
+    file: File,
+    size: u64,
+}
+
+impl EventLog {
+    pub async fn open(path: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let file = Self::open_file(&path).await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            file,
+            size,
+        })
+    }
+
+    async fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+    }
+
+    fn backup_path(&self, n: u32) -> PathBuf {
+        let mut name = self
+            .path
+            .file_name()
+            .map(OsString::from)
+            .unwrap_or_default();
+        name.push(format!(".{n}"));
+        self.path.with_file_name(name)
+    }
+
+    async fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..EVENT_LOG_BACKUPS).rev() {
+            match tokio::fs::rename(self.backup_path(n), self.backup_path(n + 1)).await {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+        tokio::fs::rename(&self.path, self.backup_path(1)).await?;
+        self.file = Self::open_file(&self.path).await?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Appends one line and flushes it to the file.
+    pub async fn append(&mut self, line: &[u8]) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.max_bytes != 0 && self.size != 0 && self.size + len > self.max_bytes {
+            self.rotate().await?;
+        }
+        self.file.write_all(line).await?;
+        self.file.write_all(b"\n").await?;
+        self.file.flush().await?;
+        self.size += len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replaces_file_held_open_by_reader() {
+        let path =
+            std::env::temp_dir().join(format!("where-am-i-atomic-{}.txt", std::process::id()));
+        write_atomic(&path, b"first").await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+
+        // Like OBS, keep the file open while it is replaced. Windows only allows replacing it
+        // once the reader lets go, which happens while the write is retrying.
+        let reader = std::fs::File::open(&path).unwrap();
+        let release = tokio::spawn(async move {
+            sleep(Duration::from_millis(30)).await;
+            drop(reader);
+        });
+        write_atomic(&path, b"second").await.unwrap();
+        release.await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+
+        let mut temp_name = path.file_name().unwrap().to_owned();
+        temp_name.push(".tmp");
+        assert!(!path.with_file_name(temp_name).exists());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::Cursor;
+
+use fast_qr::{QRBuilder, QRCode, QRCodeError, ECL};
+use image::{GrayImage, ImageFormat, Luma};
+use serde::Deserialize;
+use tracing::warn;
+
+/// How much of a QR code can be damaged while still being readable, from least to most.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum ErrorCorrection {
+    L,
+    M,
+    Q,
+    H,
+}
+
+impl ErrorCorrection {
+    const ALL: [Self; 4] = [Self::L, Self::M, Self::Q, Self::H];
+
+    fn ecl(self) -> ECL {
+        match self {
+            Self::L => ECL::L,
+            Self::M => ECL::M,
+            Self::Q => ECL::Q,
+            Self::H => ECL::H,
+        }
+    }
+}
+
+/// Encodes data at the preferred error correction level, or the most robust lower level that
+/// it fits in. Long group instance links can be too large for the higher levels.
+pub fn encode(data: &str, preferred: ErrorCorrection) -> Result<QRCode, QRCodeError> {
+    let mut levels = ErrorCorrection::ALL
+        .into_iter()
+        .rev()
+        .filter(|&level| level <= preferred);
+    let mut level = levels.next().unwrap_or(ErrorCorrection::L);
+    loop {
+        match QRBuilder::new(data).ecl(level.ecl()).build() {
+            Ok(qr) => {
+                if level != preferred {
+                    warn!(
+                        ?preferred,
+                        ?level,
+                        len = data.len(),
+                        "QR code too long, lowered error correction"
+                    );
+                }
+                return Ok(qr);
+            }
+            Err(error) => match levels.next() {
+                Some(lower) => level = lower,
+                None => return Err(error),
+            },
+        }
+    }
+}
+
+/// The width of the light border around a QR code, in modules.
+const QUIET_ZONE: usize = 4;
+
+/// Draws a QR code as a PNG image with `module_size` pixels per module.
+pub fn to_png(qr: &QRCode, module_size: u32) -> anyhow::Result<Vec<u8>> {
+    let side = (qr.size + 2 * QUIET_ZONE) as u32 * module_size;
+    let image = GrayImage::from_fn(side, side, |x, y| {
+        let column = (x / module_size) as usize;
+        let row = (y / module_size) as usize;
+        let dark = (QUIET_ZONE..QUIET_ZONE + qr.size).contains(&row)
+            && (QUIET_ZONE..QUIET_ZONE + qr.size).contains(&column)
+            && qr[row - QUIET_ZONE][column - QUIET_ZONE].value();
+        Luma([if dark { 0 } else { 255 }])
+    });
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// The number of modules across the smallest QR code.
+const MIN_SIZE: usize = 21;
+
+/// A light square the size of the smallest QR code, shown in place of a code that is hidden.
+pub fn blank_png(module_size: u32) -> anyhow::Result<Vec<u8>> {
+    let side = (MIN_SIZE + 2 * QUIET_ZONE) as u32 * module_size;
+    let image = GrayImage::from_pixel(side, side, Luma([255]));
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// An empty SVG image with the proportions of a QR code, shown in place of a code that is hidden.
+pub fn blank_svg() -> String {
+    let side = MIN_SIZE + 2 * QUIET_ZONE;
+    format!(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 {side} {side}"></svg>"#)
+}
+
+/// Parses a color like "#1e90ff", "1e90ff", "#fff" or "#1e90ff80".
+pub fn parse_hex_color(color: &str) -> Option<[u8; 4]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize, len: usize| {
+        let value = u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).ok()?;
+        // Short colors repeat each digit, so "f" is "ff".
+        Some(if len == 1 { value * 17 } else { value })
+    };
+    match hex.len() {
+        3 => Some([channel(0, 1)?, channel(1, 1)?, channel(2, 1)?, 255]),
+        6 => Some([channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 255]),
+        8 => Some([
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        ]),
+        _ => None,
+    }
+}
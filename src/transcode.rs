@@ -0,0 +1,20 @@
+use std::io::Cursor;
+
+use image::{codecs::webp::WebPEncoder, DynamicImage};
+
+/// Re-encodes an image as lossless WebP.
+///
+/// Returns `None` when the result isn't smaller than the original, which is common for
+/// photographic JPEGs since only lossless WebP encoding is available.
+pub fn to_webp(original: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+    let image = image::load_from_memory(original)?;
+    // WebP only stores 8-bit RGB and RGBA.
+    let image = match image {
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => image,
+        image if image.color().has_alpha() => image.into_rgba8().into(),
+        image => image.into_rgb8().into(),
+    };
+    let mut webp = Vec::new();
+    image.write_with_encoder(WebPEncoder::new_lossless(Cursor::new(&mut webp)))?;
+    Ok((webp.len() < original.len()).then_some(webp))
+}